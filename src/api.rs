@@ -0,0 +1,278 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use slog::{error, info, Logger};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::manifest;
+use crate::metrics::SharedMetrics;
+use crate::node::SnapshotStage;
+use crate::state::{DurationStats, SharedState};
+
+/// Progress of the snapshotting, updated by the controller as it goes
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotStatus {
+    pub phase: SnapshotStage,
+    pub snapshot_name: Option<String>,
+    pub started_at: Option<String>,
+    pub percent_complete: Option<f64>,
+    // progress of the archive being written, the total is estimated from the size of the database directories
+    pub bytes_written: Option<u64>,
+    pub estimated_total_bytes: Option<u64>,
+    pub estimated_remaining_secs: Option<u64>,
+}
+
+pub type SharedStatus = Arc<RwLock<SnapshotStatus>>;
+
+/// Whether the scheduled snapshots are suppressed, toggled through the API
+pub type SharedPaused = Arc<AtomicBool>;
+
+/// A snapshot requested of the worker owning the controller, all requests are taken one at a time in their order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotRequest {
+    // a tick of the schedule, a snapshot is only taken when the schedule is due and the snapshotting is not paused
+    Scheduled,
+    // requested through the API, taken regardless of the schedule and the pause
+    Manual,
+}
+
+/// Number of requests the snapshot queue holds, the manual requests beyond it are refused
+pub const SNAPSHOT_QUEUE_SIZE: usize = 16;
+
+/// The sending end of the snapshot queue
+pub type SnapshotQueue = mpsc::Sender<SnapshotRequest>;
+
+/// Body of the snapshot trigger endpoint
+#[derive(Debug, Serialize)]
+struct QueuedResponse {
+    queued: bool,
+}
+
+/// Body of the status endpoint, the progress together with the paused state and the recent durations
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    status: SnapshotStatus,
+    paused: bool,
+    durations: Option<DurationStats>,
+}
+
+/// Body of the pause and resume endpoints
+#[derive(Debug, Serialize)]
+struct PausedResponse {
+    paused: bool,
+}
+
+impl Default for SnapshotStatus {
+    fn default() -> Self {
+        Self {
+            phase: SnapshotStage::Idle,
+            snapshot_name: None,
+            started_at: None,
+            percent_complete: None,
+            bytes_written: None,
+            estimated_total_bytes: None,
+            estimated_remaining_secs: None,
+        }
+    }
+}
+
+/// Inputs of the liveness and readiness probes, updated by the controller
+#[derive(Clone, Debug)]
+pub struct Health {
+    pub node_reachable: bool,
+    pub last_success: Option<Instant>,
+    pub started: Instant,
+}
+
+pub type SharedHealth = Arc<RwLock<Health>>;
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            node_reachable: false,
+            last_success: None,
+            started: Instant::now(),
+        }
+    }
+}
+
+/// Body of the readiness probe
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    node_reachable: bool,
+    last_snapshot_age_secs: Option<u64>,
+}
+
+impl Health {
+    /// Ready when the node is reachable and a snapshot succeeded within the max age, or the first one still has time to
+    fn readiness(&self, max_age: Duration) -> Readiness {
+        let age = self.last_success.unwrap_or(self.started).elapsed();
+        Readiness {
+            ready: self.node_reachable && age <= max_age,
+            node_reachable: self.node_reachable,
+            last_snapshot_age_secs: self.last_success.map(|last_success| last_success.elapsed().as_secs()),
+        }
+    }
+}
+
+/// Serves the HTTP API until the server fails
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    address: SocketAddr,
+    status: SharedStatus,
+    paused: SharedPaused,
+    snapshot_queue: SnapshotQueue,
+    state: SharedState,
+    health: SharedHealth,
+    readiness_max_age: Duration,
+    metrics: SharedMetrics,
+    snapshots_target_directories: Vec<PathBuf>,
+    log: Logger,
+) {
+    let make_service = make_service_fn(move |_| {
+        let status = status.clone();
+        let paused = paused.clone();
+        let snapshot_queue = snapshot_queue.clone();
+        let state = state.clone();
+        let health = health.clone();
+        let metrics = metrics.clone();
+        let snapshots_target_directories = snapshots_target_directories.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(
+                    request,
+                    status.clone(),
+                    paused.clone(),
+                    snapshot_queue.clone(),
+                    state.clone(),
+                    health.clone(),
+                    readiness_max_age,
+                    metrics.clone(),
+                    snapshots_target_directories.clone(),
+                )
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!(log, "Failed to bind the API server to {}: {}", address, e);
+            return;
+        }
+    };
+
+    info!(log, "API server listening on {}", address);
+    if let Err(e) = server.await {
+        error!(log, "API server failed: {}", e);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle(
+    request: Request<Body>,
+    status: SharedStatus,
+    paused: SharedPaused,
+    snapshot_queue: SnapshotQueue,
+    state: SharedState,
+    health: SharedHealth,
+    readiness_max_age: Duration,
+    metrics: SharedMetrics,
+    snapshots_target_directories: Vec<PathBuf>,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/status") => {
+            let status = StatusResponse {
+                status: status.read().map(|status| status.clone()).unwrap_or_default(),
+                paused: paused.load(Ordering::Acquire),
+                durations: state.read().ok().and_then(|state| state.duration_stats()),
+            };
+            json_response(StatusCode::OK, &status)
+        }
+        // a snapshot already running is finished, only the following ones are suppressed
+        (&Method::POST, "/pause") => {
+            paused.store(true, Ordering::Release);
+            json_response(StatusCode::OK, &PausedResponse { paused: true })
+        }
+        (&Method::POST, "/resume") => {
+            paused.store(false, Ordering::Release);
+            json_response(StatusCode::OK, &PausedResponse { paused: false })
+        }
+        // queued behind the snapshot in progress and the earlier requests, the status shows when it starts
+        (&Method::POST, "/snapshot") => match snapshot_queue.try_send(SnapshotRequest::Manual) {
+            Ok(()) => json_response(StatusCode::ACCEPTED, &QueuedResponse { queued: true }),
+            Err(TrySendError::Full(_)) => json_response(StatusCode::TOO_MANY_REQUESTS, &QueuedResponse { queued: false }),
+            Err(TrySendError::Closed(_)) => json_response(StatusCode::SERVICE_UNAVAILABLE, &QueuedResponse { queued: false }),
+        },
+        // answering at all means the process is alive
+        (&Method::GET, "/healthz") => empty_response(StatusCode::OK),
+        (&Method::GET, "/readyz") => match health.read() {
+            Ok(health) => {
+                let readiness = health.readiness(readiness_max_age);
+                let status_code = if readiness.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                json_response(status_code, &readiness)
+            }
+            Err(_) => empty_response(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        (&Method::GET, "/snapshots") => {
+            let label = url::form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+                .find(|(key, _)| key == "label")
+                .map(|(_, value)| value.to_string());
+            let mut manifests: Vec<_> = snapshots_target_directories
+                .iter()
+                .flat_map(|directory| manifest::list(directory))
+                .collect();
+            manifests.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+            if let Some(label) = label {
+                manifests.retain(|manifest| manifest.label.as_ref() == Some(&label));
+            }
+            json_response(StatusCode::OK, &manifests)
+        }
+        (&Method::GET, "/metrics") => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics.render()))
+            .unwrap_or_else(|_| empty_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        _ => empty_response(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+fn json_response<T: Serialize>(status_code: StatusCode, body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status_code)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| empty_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(_) => empty_response(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+fn empty_response(status_code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status_code;
+    response
+}
@@ -0,0 +1,42 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use crate::configuration::ChecksumAlgorithm;
+use crate::manifest;
+
+/// Hashes the snapshot archive and writes the digest into a sidecar in the `sha256sum`/`b3sum` format,
+/// returns the hex digest
+pub fn write_checksum(snapshot_path: &Path, algorithm: &ChecksumAlgorithm) -> Result<String, io::Error> {
+    let digest = match algorithm {
+        ChecksumAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            io::copy(&mut BufReader::new(fs::File::open(snapshot_path)?), &mut hasher)?;
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        }
+        ChecksumAlgorithm::Blake3 => {
+            // blake3 hashes the memory mapped archive on all cores
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_mmap_rayon(snapshot_path)?;
+            hasher.finalize().to_hex().to_string()
+        }
+    };
+
+    let snapshot_name = snapshot_path.file_name().unwrap_or_default().to_string_lossy();
+    fs::write(
+        manifest::sidecar_path(snapshot_path, algorithm.extension()),
+        format!("{}  {}\n", digest, snapshot_name),
+    )?;
+
+    Ok(digest)
+}
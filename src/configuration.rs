@@ -1,15 +1,19 @@
 // Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use clap::{App, Arg};
+use chrono::format::{Item, StrftimeItems};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use std::{
-    env,
+    env, fmt,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     str::FromStr,
 };
 
 use url::Url;
 
+use crate::node::SnapshotSettings;
+
 #[derive(Clone, Debug)]
 pub struct TezedgeSnapshotEnvironment {
     // logging level
@@ -33,8 +37,8 @@ pub struct TezedgeSnapshotEnvironment {
     // path to the running tezedge node database directory
     pub tezedge_database_directory: PathBuf,
 
-    // maximum number of snapshots kept on the machine
-    pub snapshot_capacity: usize,
+    // maximum number of snapshots of each type kept on the machine
+    pub snapshot_capacity: SnapshotCapacity,
 
     // frequency of the snapshots in seconds
     pub snapshot_frequency: u64,
@@ -48,13 +52,255 @@ pub struct TezedgeSnapshotEnvironment {
     /// use this image to create the full snapshotting container
     pub full_snapshot_image: String,
 
+    // time in seconds the full snapshotting container has to finish the export
+    pub full_snapshot_timeout: u64,
+
     pub context_type: ContextType,
 
+    // compression used for the snapshot archives
+    pub compression: CompressionType,
+
+    // produce a compressed tarball or a plain snapshot directory
+    pub format: SnapshotFormat,
+
+    // minimal free space in bytes on the target required to start a snapshot
+    pub min_free_space: u64,
+
+    // take a new snapshot even when the head hash did not change since the last one
+    pub allow_same_head: bool,
+
+    // path to a fast local directory the archives are assembled in before moving them to the target
+    pub scratch_directory: Option<PathBuf>,
+
+    // grace period in seconds after a node restart during which no snapshot is taken
+    pub post_restart_grace: u64,
+
+    // number of threads used for compression, only xz compresses in parallel
+    pub compression_threads: u32,
+
+    // path to a secondary directory every finalized snapshot is copied to
+    pub mirror_directory: Option<PathBuf>,
+
+    // maximum number of snapshots kept in the mirror directory, defaults to snapshot_capacity
+    pub mirror_capacity: Option<usize>,
+
+    // directory the latest snapshot of each type is kept extracted in, ready for a node to start from
+    pub warm_standby_dir: Option<PathBuf>,
+
+    // query the node's version endpoint at startup to confirm the url points to a tezos RPC
+    pub check_rpc_version: bool,
+
+    // tag appended to the snapshot names, e.g. the environment
+    pub name_suffix: Option<String>,
+
+    // boot a throwaway node from each new snapshot to confirm it reaches a head
+    pub validate_snapshot: bool,
+
+    // rpc port of the throwaway validation node, its p2p port is the next one
+    pub validation_rpc_port: u16,
+
+    // time in seconds the validation node has to serve a head
+    pub validation_timeout: u64,
+
+    // address the HTTP API listens on, disabled when not set
+    pub api_address: Option<SocketAddr>,
+
+    // size in bytes of the write buffer in front of the archive file
+    pub compression_buffer_size: usize,
+
+    // interval in seconds between two polls of the node's rpc, checks in between reuse the last result
+    pub rpc_poll_interval: u64,
+
+    // start the node container when it is found stopped
+    pub manage_node_lifecycle: bool,
+
+    // only warn at startup when the node or monitoring container does not exist
+    pub allow_missing_container: bool,
+
+    // url snapshotting events are posted to
+    pub webhook_url: Option<Url>,
+
+    // NATS server the snapshot lifecycle events are published to, and their subject
+    pub event_sink: Option<Url>,
+    pub event_subject: String,
+
+    // trackers announced in the .torrent sidecars, and the download urls of the target directory used as web seeds
+    pub torrent_trackers: Vec<Url>,
+    pub torrent_web_seeds: Vec<Url>,
+
+    // number of consecutive failed snapshots after which the failure is escalated
+    pub failure_escalation_threshold: u32,
+
+    // strftime format of the timestamp in the snapshot names
+    pub name_timestamp_format: String,
+
+    // how the snapshot names are built, tezedge or octez style
+    pub naming_scheme: NamingScheme,
+
+    // checksum written next to each archive, none when not set
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+
+    // size budget in bytes of each snapshot directory
+    pub max_total_size: Option<u64>,
+
+    // only connect to the node over IPv6
+    pub prefer_ipv6: bool,
+
+    // host to address overrides used instead of the resolver when connecting to the node
+    pub resolve_overrides: Vec<(String, SocketAddr)>,
+
+    // suffix appended to the container names, {context} and {network} are substituted
+    pub container_name_suffix: String,
+
+    // suffix appended to the monitoring container name instead, the container name suffix when not set
+    pub monitoring_container_name_suffix: Option<String>,
+
+    // free-form label recorded in the manifests
+    pub label: Option<String>,
+
+    // also put the label into the snapshot names
+    pub label_in_name: bool,
+
+    // sync the snapshots to the disk before publishing them under their final name
+    pub fsync: bool,
+
+    // uid and gid the created snapshots are handed over to
+    pub chown: Option<(u32, u32)>,
+
+    // which directories of the node database directory make up a snapshot
+    pub node_layout: NodeLayout,
+
+    // time in seconds the node may stay unreachable before its container is restarted, disabled when not set
+    pub watchdog_timeout: Option<u64>,
+
+    // time in seconds the node may stay unreachable before the warnings start, disabled when 0
+    pub unreachable_grace: u64,
+
+    // never remove snapshots automatically, the retention is managed externally
+    pub no_roll: bool,
+
+    // PEM encoded CA certificate the node's rpc certificate is validated against
+    pub rpc_ca_cert: Option<PathBuf>,
+
+    // accept any certificate of the node's rpc
+    pub rpc_insecure: bool,
+
+    // leave the context lock file of the stopped node in place
+    pub skip_lock_removal: bool,
+
+    // fail the snapshot instead of force removing a context lock the stopped node did not release
+    pub strict_lock: bool,
+
+    // size in bytes of the parts the archives are split into
+    pub split_size: Option<u64>,
+
+    // time in seconds the restarted node has to serve its head again
+    pub recovery_timeout: u64,
+
+    // keep snapshotting after a fatal snapshot error instead of stopping
+    pub resilient: bool,
+
+    // time in seconds to wait after a fatal snapshot error in resilient mode
+    pub resilient_backoff: u64,
+
+    // named pipe the archive snapshots are streamed into instead of the target directory
+    pub output_pipe: Option<PathBuf>,
+
+    // time in seconds to wait before retrying a failed node restart, doubled with each consecutive failure
+    pub restart_cooldown: u64,
+
+    // number of consecutive failed node restarts after which the failure is escalated
+    pub restart_failure_threshold: u32,
+
+    // remove the transient bootstrap database files from the full snapshots before archiving
+    pub clean_bootstrap_before_full: bool,
+
+    // time in seconds to wait for the stopped node to release the context lock before removing it
+    pub lock_release_timeout: u64,
+
+    // further target directories, each snapshot is placed in the target directory with the most free space
+    pub additional_target_directories: Vec<PathBuf>,
+
+    // time in seconds a docker operation may take before it is considered failed
+    pub docker_timeout: u64,
+
+    // number of retries of a docker operation failing on a busy daemon
+    pub docker_retries: u32,
+
+    // rpc of the node checkpointing its context, archive snapshots are taken without stopping the node when it succeeds
+    pub checkpoint_rpc_path: Option<String>,
+
+    // how the subcommands print their results
+    pub output: OutputFormat,
+
+    // multiple of the snapshot frequency the last successful snapshot may be old for the instance to be ready
+    pub readiness_tolerance: f64,
+
+    // compression of the archive snapshots, the global compression when not set
+    pub archive_compression: Option<CompressionSetting>,
+
+    // compression of the full snapshots, the global compression when not set
+    pub full_compression: Option<CompressionSetting>,
+
+    // time in seconds the stopped node container has to be confirmed not running before snapshotting
+    pub stop_confirm_timeout: u64,
+
+    // time in seconds to wait after the node container stopped before reading the database
+    pub post_stop_delay: u64,
+
+    // bind sources on the docker host of the database and the primary target directory, for the helper containers
+    pub host_data_path: Option<PathBuf>,
+    pub host_snapshots_path: Option<PathBuf>,
+
+    // time in seconds the compression of an archive should fit in, the level is lowered when it is estimated not to
+    pub compression_time_budget: Option<u64>,
+
+    // niceness of the compressing thread, yielding the CPU to the restarted node
+    pub compression_nice: Option<i32>,
+
+    // shell command the uncompressed tar stream is piped through instead of the built in compression
+    pub external_compressor: Option<String>,
+    pub external_compressor_extension: String,
+
+    // globs of the paths below the context directory the archives are limited to, the whole context when empty
+    pub context_include: Vec<String>,
+
+    // time in seconds that has to pass between the starts of any two snapshots
+    pub min_interval: u64,
+
+    // remove the stopped helper containers left behind by failed snapshots at startup
+    pub cleanup_containers_on_start: bool,
+
+    // docker API version (major, minor) to talk to the daemon with, the client default when not set
+    pub docker_api_version: Option<(usize, usize)>,
+
+    // identifier of this instance in the logs and metrics, the hostname by default
+    pub instance_id: String,
+
+    // what the app should do, run the snapshotting daemon by default
+    pub command: Command,
+
     // TODO: add options for snapshot frequency in blocks
     // TODO: add options for snapshot frequency: daily, weekly, ... Note: in combination of timestamp?
     // TODO: add options for concrete levels to snapshot on
 }
 
+#[derive(Clone, Debug)]
+pub enum Command {
+    // take snapshots in the configured interval
+    Run,
+    // apply the retention to the existing snapshots right away
+    Prune { dry_run: bool },
+    // print the versions of the tool and its environment for bug reports
+    Version,
+    // print the estimated snapshot size without stopping the node
+    Estimate,
+    // remove the stopped helper containers left behind by failed snapshots
+    CleanupContainers { dry_run: bool },
+    // print the head of the node to check it is reachable
+    TestRpc,
+}
+
 #[derive(Clone, Debug)]
 pub enum SnapshotType {
     Archive,
@@ -68,6 +314,67 @@ pub enum ContextType {
     Tezedge,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompressionType {
+    Gzip,
+    Xz,
+}
+
+#[derive(Clone, Debug)]
+pub enum SnapshotFormat {
+    Tar,
+    Dir,
+    Members,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+#[derive(Clone, Debug)]
+pub enum NodeLayout {
+    V1,
+}
+
+/// A compression with an optional level, e.g. `xz:9`
+#[derive(Clone, Debug)]
+pub struct CompressionSetting {
+    pub compression: CompressionType,
+    pub level: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub enum NamingScheme {
+    // tezedge_<network>_<timestamp>_<block hash>_<context>
+    Tezedge,
+    // <network>-<block hash>-<level>, as octez names its exported snapshots
+    Octez,
+}
+
+#[derive(Clone, Debug)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotCapacity {
+    pub archive: usize,
+    pub full: usize,
+}
+
+impl SnapshotCapacity {
+    /// Capacity of the snapshot type, archive or full
+    pub fn of(&self, kind: &str) -> usize {
+        match kind {
+            "archive" => self.archive,
+            _ => self.full,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TypeNotFound {}
 
@@ -96,11 +403,162 @@ impl FromStr for ContextType {
     }
 }
 
-impl ToString for ContextType {
-    fn to_string(&self) -> String {
+impl fmt::Display for ContextType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContextType::Irmin => write!(f, "irmin"),
+            ContextType::Tezedge => write!(f, "tezedge"),
+        }
+    }
+}
+
+impl FromStr for CompressionType {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(CompressionType::Gzip),
+            "xz" => Ok(CompressionType::Xz),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar" => Ok(SnapshotFormat::Tar),
+            "dir" => Ok(SnapshotFormat::Dir),
+            "members" => Ok(SnapshotFormat::Members),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl ChecksumAlgorithm {
+    /// Extension of the checksum sidecar
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+impl FromStr for NodeLayout {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(NodeLayout::V1),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl FromStr for CompressionSetting {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (compression, level) = match s.split_once(':') {
+            Some((compression, level)) => (compression, Some(level.parse::<u32>().map_err(|_| TypeNotFound {})?)),
+            None => (s, None),
+        };
+        // both gzip and xz levels range from 0 to 9
+        if level.map(|level| level > 9).unwrap_or(false) {
+            return Err(TypeNotFound {});
+        }
+        Ok(CompressionSetting {
+            compression: compression.parse::<CompressionType>()?,
+            level,
+        })
+    }
+}
+
+impl FromStr for NamingScheme {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tezedge" => Ok(NamingScheme::Tezedge),
+            "octez" => Ok(NamingScheme::Octez),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = TypeNotFound;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(TypeNotFound {}),
+        }
+    }
+}
+
+impl fmt::Display for NodeLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeLayout::V1 => write!(f, "v1"),
+        }
+    }
+}
+
+impl NodeLayout {
+    /// Directories of the node database directory that make up a snapshot
+    pub fn members(&self) -> &'static [&'static str] {
+        match self {
+            NodeLayout::V1 => &["context", "bootstrap_db"],
+        }
+    }
+}
+
+impl fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionType::Gzip => write!(f, "gzip"),
+            CompressionType::Xz => write!(f, "xz"),
+        }
+    }
+}
+
+impl CompressionType {
+    /// File extension appended to the snapshot name, gzip archives keep the original extension-less naming
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            CompressionType::Gzip => None,
+            CompressionType::Xz => Some("tar.xz"),
+        }
+    }
+
+    /// Rough compressed to uncompressed size ratio of a tezedge database, for estimates only
+    pub fn estimated_ratio(&self) -> f64 {
         match self {
-            ContextType::Irmin => String::from("irmin"),
-            ContextType::Tezedge => String::from("tezedge"),
+            CompressionType::Gzip => 0.6,
+            CompressionType::Xz => 0.45,
         }
     }
 }
@@ -113,6 +571,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("tezedge-database-directory")
                 .long("tezedge-database-directory")
+                .env("TEZEDGE_SNAPSHOTS_TEZEDGE_DATABASE_DIRECTORY")
                 .takes_value(true)
                 .value_name("PATH")
                 .help("The path to the running tezedge node database directory")
@@ -127,6 +586,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("snapshots-target-directory")
                 .long("snapshots-target-directory")
+                .env("TEZEDGE_SNAPSHOTS_SNAPSHOTS_TARGET_DIRECTORY")
                 .takes_value(true)
                 .value_name("PATH")
                 .help("The path to the target directory for the snapshots")
@@ -141,6 +601,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("node-container-name")
                 .long("node-container-name")
+                .env("TEZEDGE_SNAPSHOTS_NODE_CONTAINER_NAME")
                 .takes_value(true)
                 .value_name("STRING")
                 .help("The name of the container the tezedge node resides in"),
@@ -148,6 +609,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("network")
                 .long("network")
+                .env("TEZEDGE_SNAPSHOTS_NETWORK")
                 .takes_value(true)
                 .value_name("STRING")
                 .help("The name of network tezedge is connecting to"),
@@ -155,6 +617,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("monitoring-container-name")
                 .long("monitoring-container-name")
+                .env("TEZEDGE_SNAPSHOTS_MONITORING_CONTAINER_NAME")
                 .takes_value(true)
                 .value_name("STRING")
                 .help("The name of the container the tezedge monitoring resides in"),
@@ -162,6 +625,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("tezedge-node-url")
                 .long("tezedge-node-url")
+                .env("TEZEDGE_SNAPSHOTS_TEZEDGE_NODE_URL")
                 .takes_value(true)
                 .value_name("URL")
                 .help("The url to the tezedge node for the snapshots"),
@@ -169,13 +633,15 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("snapshot-capacity")
                 .long("snapshot-capacity")
+                .env("TEZEDGE_SNAPSHOTS_SNAPSHOT_CAPACITY")
                 .takes_value(true)
-                .value_name("USIZE")
-                .help("The maximum number of snapshots kept on the machine"),
+                .value_name("USIZE|TYPE=USIZE,...")
+                .help("The maximum number of snapshots of each type kept on the machine, e.g. 7 or archive=3,full=14"),
         )
         .arg(
             Arg::with_name("snapshot-frequency")
                 .long("snapshot-frequency")
+                .env("TEZEDGE_SNAPSHOTS_SNAPSHOT_FREQUENCY")
                 .takes_value(true)
                 .value_name("U64")
                 .help("The frequency of the snapshots in seconds"),
@@ -183,6 +649,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("check-interval")
                 .long("check-interval")
+                .env("TEZEDGE_SNAPSHOTS_CHECK_INTERVAL")
                 .takes_value(true)
                 .value_name("U64")
                 .help("The interval in seconds to perform the check for can_snapshot"),
@@ -190,6 +657,7 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("snapshot-type")
                 .long("snapshot-type")
+                .env("TEZEDGE_SNAPSHOTS_SNAPSHOT_TYPE")
                 .takes_value(true)
                 .value_name("SnapshotType")
                 .help("Type of the snapshots"),
@@ -197,76 +665,861 @@ fn tezedge_snapshots_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("context-type")
                 .long("context-type")
+                .env("TEZEDGE_SNAPSHOTS_CONTEXT_TYPE")
                 .takes_value(true)
                 .value_name("ContextType")
+                .possible_values(&["irmin", "tezedge"])
                 .help("Type of the context"),
         )
         .arg(
-            Arg::with_name("full-snapshot-image")
-                .long("full-snapshot-image")
+            Arg::with_name("compression")
+                .long("compression")
+                .env("TEZEDGE_SNAPSHOTS_COMPRESSION")
                 .takes_value(true)
-                .value_name("STRING")
-                .help("The name of the tezedge image to use for the full snapshots"),
+                .value_name("CompressionType")
+                .possible_values(&["gzip", "xz"])
+                .help("Compression used for the snapshot archives"),
         )
         .arg(
-            Arg::with_name("log-level")
-                .long("log-level")
+            Arg::with_name("format")
+                .long("format")
+                .env("TEZEDGE_SNAPSHOTS_FORMAT")
                 .takes_value(true)
-                .value_name("SLOG LEVEL")
-                .possible_values(&["critical", "error", "warn", "info", "debug", "trace"])
-                .help("Set logging level"),
-        );
-
-    app
-}
-
-impl TezedgeSnapshotEnvironment {
-    pub fn from_args() -> Self {
-        let app = tezedge_snapshots_app();
-        let args = app.clone().get_matches();
-
-        Self {
-            log_level: args
-                .value_of("log-level")
-                .unwrap_or("info")
-                .parse::<slog::Level>()
-                .expect("Was expecting one value from slog::Level"),
-
-            check_interval: args
-                .value_of("check-interval")
-                .unwrap_or("5")
-                .parse::<u64>()
-                .expect("Expected u64 value of seconds"),
-
-            tezedge_node_url: args
-                .value_of("tezedge-node-url")
-                .unwrap_or("http://localhost:18732")
-                .parse::<Url>()
-                .expect("Was expecting a valid url"),
-            node_container_name: args
-                .value_of("node-container-name")
-                .unwrap_or("tezedge-node")
-                .to_string(),
-            monitoring_container_name: args
-                .value_of("monitoring-container-name")
-                .unwrap_or("tezedge-node-monitoring")
-                .to_string(),
-            network: args.value_of("network").unwrap_or("network").to_string(),
-            snapshots_target_directory: args
-                .value_of("snapshots-target-directory")
-                .unwrap_or("/tmp/snapshots")
-                .parse::<PathBuf>()
-                .expect("The provided path is invalid"),
-            tezedge_database_directory: args
-                .value_of("tezedge-database-directory")
-                .unwrap_or("/tmp/tezedge")
-                .parse::<PathBuf>()
-                .expect("The provided path is invalid"),
-            snapshot_capacity: args
-                .value_of("snapshot-capacity")
-                .unwrap_or("7")
-                .parse::<usize>()
-                .expect("Expected usize value"),
+                .value_name("SnapshotFormat")
+                .possible_values(&["tar", "dir", "members"])
+                .help("Produce a compressed tarball (tar), an uncompressed snapshot directory (dir) or a directory with a tarball per database directory (members)"),
+        )
+        .arg(
+            Arg::with_name("min-free-space")
+                .long("min-free-space")
+                .env("TEZEDGE_SNAPSHOTS_MIN_FREE_SPACE")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The minimal free space in bytes on the snapshot target required to start a snapshot"),
+        )
+        .arg(
+            Arg::with_name("allow-same-head")
+                .long("allow-same-head")
+                .takes_value(false)
+                .help("Take a new snapshot even when the head hash did not change since the last snapshot"),
+        )
+        .arg(
+            Arg::with_name("scratch-dir")
+                .long("scratch-dir")
+                .env("TEZEDGE_SNAPSHOTS_SCRATCH_DIR")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("The path to a fast local directory the archives are assembled in before being moved to the target")
+                .validator(|p| {
+                    if Path::new(&p).exists() {
+                        Ok(())
+                    } else {
+                        Err(format!("Scratch directory path not found '{}'", p))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("post-restart-grace")
+                .long("post-restart-grace")
+                .env("TEZEDGE_SNAPSHOTS_POST_RESTART_GRACE")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The grace period in seconds after a node restart during which no snapshot is taken"),
+        )
+        .arg(
+            Arg::with_name("compression-threads")
+                .long("compression-threads")
+                .env("TEZEDGE_SNAPSHOTS_COMPRESSION_THREADS")
+                .takes_value(true)
+                .value_name("U32|auto")
+                .help("The number of compression threads (xz only), auto uses the available cores minus one")
+                .validator(|v| {
+                    if v == "auto" || v.parse::<u32>().is_ok() {
+                        Ok(())
+                    } else {
+                        Err(format!("Expected a number of threads or auto, got '{}'", v))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("mirror-directory")
+                .long("mirror-directory")
+                .env("TEZEDGE_SNAPSHOTS_MIRROR_DIRECTORY")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("The path to a secondary directory every finalized snapshot is copied to")
+                .validator(|p| {
+                    if Path::new(&p).exists() {
+                        Ok(())
+                    } else {
+                        Err(format!("Mirror directory path not found '{}'", p))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("mirror-capacity")
+                .long("mirror-capacity")
+                .env("TEZEDGE_SNAPSHOTS_MIRROR_CAPACITY")
+                .takes_value(true)
+                .value_name("USIZE")
+                .help("The maximum number of snapshots kept in the mirror directory"),
+        )
+        .arg(
+            Arg::with_name("warm-standby-dir")
+                .long("warm-standby-dir")
+                .env("TEZEDGE_SNAPSHOTS_WARM_STANDBY_DIR")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with("external-compressor")
+                .help("Directory the latest archive snapshot is kept extracted in, replaced after each snapshot"),
+        )
+        .arg(
+            Arg::with_name("check-rpc-version")
+                .long("check-rpc-version")
+                .takes_value(false)
+                .help("Query the node's version endpoint at startup and warn if it does not look like a tezedge/octez RPC"),
+        )
+        .arg(
+            Arg::with_name("name-suffix")
+                .long("name-suffix")
+                .env("TEZEDGE_SNAPSHOTS_NAME_SUFFIX")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("A tag appended to the snapshot names before the extension, e.g. the environment")
+                .validator(|s| {
+                    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                        Ok(())
+                    } else {
+                        Err(format!("The name suffix may only contain alphanumeric characters and '-', got '{}'", s))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("validate-snapshot")
+                .long("validate-snapshot")
+                .takes_value(false)
+                .help("Boot a throwaway node from each new snapshot and mark it validated in its manifest once it serves a head"),
+        )
+        .arg(
+            Arg::with_name("validation-rpc-port")
+                .long("validation-rpc-port")
+                .env("TEZEDGE_SNAPSHOTS_VALIDATION_RPC_PORT")
+                .takes_value(true)
+                .value_name("U16")
+                .help("The rpc port of the throwaway validation node, its p2p port is the next one"),
+        )
+        .arg(
+            Arg::with_name("validation-timeout")
+                .long("validation-timeout")
+                .env("TEZEDGE_SNAPSHOTS_VALIDATION_TIMEOUT")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The time in seconds the validation node has to serve a head"),
+        )
+        .arg(
+            Arg::with_name("api-address")
+                .long("api-address")
+                .env("TEZEDGE_SNAPSHOTS_API_ADDRESS")
+                .takes_value(true)
+                .value_name("SOCKET ADDRESS")
+                .help("The address the HTTP API listens on, e.g. 0.0.0.0:8080. The API is disabled when not set")
+                .validator(|a| {
+                    a.parse::<SocketAddr>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid socket address '{}': {}", a, e))
+                }),
+        )
+        .arg(
+            Arg::with_name("compression-buffer-size")
+                .long("compression-buffer-size")
+                .env("TEZEDGE_SNAPSHOTS_COMPRESSION_BUFFER_SIZE")
+                .takes_value(true)
+                .value_name("USIZE")
+                .help("The size in bytes of the write buffer in front of the archive file"),
+        )
+        .arg(
+            Arg::with_name("rpc-poll-interval")
+                .long("rpc-poll-interval")
+                .env("TEZEDGE_SNAPSHOTS_RPC_POLL_INTERVAL")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The interval in seconds between two polls of the node's rpc, checks in between reuse the last result"),
+        )
+        .arg(
+            Arg::with_name("manage-node-lifecycle")
+                .long("manage-node-lifecycle")
+                .takes_value(false)
+                .help("Start the node and monitoring containers when they are found stopped at startup"),
+        )
+        .arg(
+            Arg::with_name("allow-missing-container")
+                .long("allow-missing-container")
+                .takes_value(false)
+                .help("Only warn instead of exiting when the node or monitoring container does not exist at startup"),
+        )
+        .arg(
+            Arg::with_name("webhook-url")
+                .long("webhook-url")
+                .env("TEZEDGE_SNAPSHOTS_WEBHOOK_URL")
+                .takes_value(true)
+                .value_name("URL")
+                .help("The url snapshotting events are posted to as JSON"),
+        )
+        .arg(
+            Arg::with_name("webhook-url-file")
+                .long("webhook-url-file")
+                .env("TEZEDGE_SNAPSHOTS_WEBHOOK_URL_FILE")
+                .takes_value(true)
+                .value_name("PATH")
+                .conflicts_with("webhook-url")
+                .help("A file the webhook url is read from, keeps tokens embedded in the url out of the process listing"),
+        )
+        .arg(
+            Arg::with_name("event-sink")
+                .long("event-sink")
+                .env("TEZEDGE_SNAPSHOTS_EVENT_SINK")
+                .takes_value(true)
+                .value_name("URL")
+                .help("NATS server the snapshot lifecycle events are published to, e.g. nats://localhost:4222, needs the event-sink feature"),
+        )
+        .arg(
+            Arg::with_name("event-subject")
+                .long("event-subject")
+                .env("TEZEDGE_SNAPSHOTS_EVENT_SUBJECT")
+                .takes_value(true)
+                .value_name("SUBJECT")
+                .help("Subject the snapshot lifecycle events are published to"),
+        )
+        .arg(
+            Arg::with_name("torrent-tracker")
+                .long("torrent-tracker")
+                .env("TEZEDGE_SNAPSHOTS_TORRENT_TRACKER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("URL")
+                .help("A tracker announced in the .torrent written next to each snapshot, can be repeated (needs the torrent feature)"),
+        )
+        .arg(
+            Arg::with_name("torrent-web-seed")
+                .long("torrent-web-seed")
+                .env("TEZEDGE_SNAPSHOTS_TORRENT_WEB_SEED")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("URL")
+                .help("The url the target directory is served from over HTTP, used as a web seed of the .torrent written next to each snapshot, can be repeated (needs the torrent feature)"),
+        )
+        .arg(
+            Arg::with_name("failure-escalation-threshold")
+                .long("failure-escalation-threshold")
+                .env("TEZEDGE_SNAPSHOTS_FAILURE_ESCALATION_THRESHOLD")
+                .takes_value(true)
+                .value_name("U32")
+                .help("The number of consecutive failed snapshots after which the failure is escalated"),
+        )
+        .arg(
+            Arg::with_name("name-timestamp-format")
+                .long("name-timestamp-format")
+                .env("TEZEDGE_SNAPSHOTS_NAME_TIMESTAMP_FORMAT")
+                .takes_value(true)
+                .value_name("STRFTIME")
+                .help("The strftime format of the UTC timestamp in the snapshot names")
+                .validator(|f| {
+                    if f.contains('/') || StrftimeItems::new(&f).any(|item| item == Item::Error) {
+                        Err(format!("Invalid snapshot name timestamp format '{}'", f))
+                    } else {
+                        Ok(())
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("naming-scheme")
+                .long("naming-scheme")
+                .env("TEZEDGE_SNAPSHOTS_NAMING_SCHEME")
+                .takes_value(true)
+                .value_name("NamingScheme")
+                .help("How the snapshot names are built, tezedge or octez (<network>-<block hash>-<level>)"),
+        )
+        .arg(
+            Arg::with_name("checksum-algorithm")
+                .long("checksum-algorithm")
+                .env("TEZEDGE_SNAPSHOTS_CHECKSUM_ALGORITHM")
+                .takes_value(true)
+                .value_name("ChecksumAlgorithm")
+                .possible_values(&["sha256", "blake3"])
+                .help("Write a checksum sidecar next to each archive using the algorithm"),
+        )
+        .arg(
+            Arg::with_name("max-total-size")
+                .long("max-total-size")
+                .env("TEZEDGE_SNAPSHOTS_MAX_TOTAL_SIZE")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The size budget in bytes of each snapshot directory, the oldest snapshots are rolled to fit it"),
+        )
+        .arg(
+            Arg::with_name("prefer-ipv6")
+                .long("prefer-ipv6")
+                .takes_value(false)
+                .help("Only connect to the node RPC over IPv6"),
+        )
+        .arg(
+            Arg::with_name("resolve")
+                .long("resolve")
+                .env("TEZEDGE_SNAPSHOTS_RESOLVE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("HOST=IP")
+                .help("Resolve the host of the node url to the given address instead of using DNS, can be repeated"),
+        )
+        .arg(
+            Arg::with_name("container-name-suffix")
+                .long("container-name-suffix")
+                .env("TEZEDGE_SNAPSHOTS_CONTAINER_NAME_SUFFIX")
+                .takes_value(true)
+                .empty_values(true)
+                .value_name("SUFFIX")
+                .help("The suffix appended to the node and monitoring container names, {context} and {network} are substituted. An empty value uses the names as they are"),
+        )
+        .arg(
+            Arg::with_name("monitoring-container-name-suffix")
+                .long("monitoring-container-name-suffix")
+                .env("TEZEDGE_SNAPSHOTS_MONITORING_CONTAINER_NAME_SUFFIX")
+                .takes_value(true)
+                .empty_values(true)
+                .value_name("SUFFIX")
+                .help("The suffix appended to the monitoring container name instead of the container name suffix, {context} and {network} are substituted"),
+        )
+        .arg(
+            Arg::with_name("label")
+                .long("label")
+                .env("TEZEDGE_SNAPSHOTS_LABEL")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("A label recorded in the snapshot manifests, e.g. nightly or pre-upgrade")
+                .validator(|s| {
+                    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                        Ok(())
+                    } else {
+                        Err(format!("The label may only contain alphanumeric characters and '-', got '{}'", s))
+                    }
+                }),
+        )
+        .arg(
+            Arg::with_name("label-in-name")
+                .long("label-in-name")
+                .takes_value(false)
+                .requires("label")
+                .help("Also put the label into the snapshot names"),
+        )
+        .arg(
+            Arg::with_name("no-fsync")
+                .long("no-fsync")
+                .takes_value(false)
+                .help("Do not sync the snapshots to the disk before publishing them, faster but a power loss may leave a truncated snapshot"),
+        )
+        .arg(
+            Arg::with_name("chown")
+                .long("chown")
+                .env("TEZEDGE_SNAPSHOTS_CHOWN")
+                .takes_value(true)
+                .value_name("UID:GID")
+                .help("Set the owner of the created snapshots, their sidecars and directories"),
+        )
+        .arg(
+            Arg::with_name("node-layout")
+                .long("node-layout")
+                .env("TEZEDGE_SNAPSHOTS_NODE_LAYOUT")
+                .takes_value(true)
+                .value_name("NodeLayout")
+                .possible_values(&["v1"])
+                .help("The database directory layout of the node version, decides which directories make up a snapshot"),
+        )
+        .arg(
+            Arg::with_name("watchdog-timeout")
+                .long("watchdog-timeout")
+                .env("TEZEDGE_SNAPSHOTS_WATCHDOG_TIMEOUT")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The time in seconds the node may stay unreachable before its container is restarted"),
+        )
+        .arg(
+            Arg::with_name("unreachable-grace")
+                .long("unreachable-grace")
+                .env("TEZEDGE_SNAPSHOTS_UNREACHABLE_GRACE")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("The time the node may stay unreachable before escalating warnings are logged, 0 disables them"),
+        )
+        .arg(
+            Arg::with_name("no-roll")
+                .long("no-roll")
+                .takes_value(false)
+                .help("Never remove snapshots automatically, only warn when a directory grows over its capacity"),
+        )
+        .arg(
+            Arg::with_name("rpc-ca-cert")
+                .long("rpc-ca-cert")
+                .env("TEZEDGE_SNAPSHOTS_RPC_CA_CERT")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("A PEM encoded CA certificate trusted in addition to the system ones when the node rpc is served over https"),
+        )
+        .arg(
+            Arg::with_name("rpc-insecure")
+                .long("rpc-insecure")
+                .takes_value(false)
+                .help("Accept any certificate of the node rpc, e.g. a self-signed one in development"),
+        )
+        .arg(
+            Arg::with_name("skip-lock-removal")
+                .long("skip-lock-removal")
+                .takes_value(false)
+                .help("Do not remove the context/index/lock file of the stopped node before snapshotting"),
+        )
+        .arg(
+            Arg::with_name("strict-lock")
+                .long("strict-lock")
+                .takes_value(false)
+                .help("Fail the snapshot and restart the node instead of force removing a context lock the stopped node did not release"),
+        )
+        .arg(
+            Arg::with_name("split-size")
+                .long("split-size")
+                .env("TEZEDGE_SNAPSHOTS_SPLIT_SIZE")
+                .takes_value(true)
+                .value_name("U64")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(size) if size > 0 => Ok(()),
+                    _ => Err(format!("Expected a positive number of bytes, got '{}'", s)),
+                })
+                .help("Split the archives into numbered parts of at most this many bytes"),
+        )
+        .arg(
+            Arg::with_name("recovery-timeout")
+                .long("recovery-timeout")
+                .env("TEZEDGE_SNAPSHOTS_RECOVERY_TIMEOUT")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The time in seconds the node restarted after a snapshot has to serve its head again"),
+        )
+        .arg(
+            Arg::with_name("resilient")
+                .long("resilient")
+                .takes_value(false)
+                .help("Keep snapshotting after a fatal snapshot error, the node is started if it was left stopped"),
+        )
+        .arg(
+            Arg::with_name("resilient-backoff")
+                .long("resilient-backoff")
+                .env("TEZEDGE_SNAPSHOTS_RESILIENT_BACKOFF")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The time in seconds to wait after a fatal snapshot error in resilient mode"),
+        )
+        .arg(
+            Arg::with_name("output-pipe")
+                .long("output-pipe")
+                .env("TEZEDGE_SNAPSHOTS_OUTPUT_PIPE")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("An existing named pipe to stream the archive snapshots into instead of the target directory"),
+        )
+        .arg(
+            Arg::with_name("restart-cooldown")
+                .long("restart-cooldown")
+                .env("TEZEDGE_SNAPSHOTS_RESTART_COOLDOWN")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time to wait before retrying a failed node restart, doubled with each consecutive failure"),
+        )
+        .arg(
+            Arg::with_name("restart-failure-threshold")
+                .long("restart-failure-threshold")
+                .env("TEZEDGE_SNAPSHOTS_RESTART_FAILURE_THRESHOLD")
+                .takes_value(true)
+                .value_name("U32")
+                .help("The number of consecutive failed node restarts after which the failure is escalated"),
+        )
+        .arg(
+            Arg::with_name("clean-bootstrap-before-full")
+                .long("clean-bootstrap-before-full")
+                .takes_value(false)
+                .help("Remove the transient bootstrap database files (RocksDB logs and locks) from the full snapshots before archiving"),
+        )
+        .arg(
+            Arg::with_name("lock-release-timeout")
+                .long("lock-release-timeout")
+                .env("TEZEDGE_SNAPSHOTS_LOCK_RELEASE_TIMEOUT")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time to wait for the stopped node to release the context lock before it is force removed"),
+        )
+        .arg(
+            Arg::with_name("additional-target-directory")
+                .long("additional-target-directory")
+                .env("TEZEDGE_SNAPSHOTS_ADDITIONAL_TARGET_DIRECTORY")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATH")
+                .help("A further target directory, each snapshot is placed in the target directory with the most free space, can be repeated"),
+        )
+        .arg(
+            Arg::with_name("docker-timeout")
+                .long("docker-timeout")
+                .env("TEZEDGE_SNAPSHOTS_DOCKER_TIMEOUT")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time a docker operation may take before it is considered failed"),
+        )
+        .arg(
+            Arg::with_name("docker-retries")
+                .long("docker-retries")
+                .env("TEZEDGE_SNAPSHOTS_DOCKER_RETRIES")
+                .takes_value(true)
+                .value_name("U32")
+                .help("The number of retries of a docker operation timing out or failing on a busy daemon"),
+        )
+        .arg(
+            Arg::with_name("checkpoint-rpc-path")
+                .long("checkpoint-rpc-path")
+                .env("TEZEDGE_SNAPSHOTS_CHECKPOINT_RPC_PATH")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("The node rpc checkpointing its context, the archive snapshots are taken without stopping the node when it succeeds"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .env("TEZEDGE_SNAPSHOTS_OUTPUT")
+                .takes_value(true)
+                .value_name("OutputFormat")
+                .help("How the subcommands print their results, text or json"),
+        )
+        .arg(
+            Arg::with_name("readiness-tolerance")
+                .long("readiness-tolerance")
+                .env("TEZEDGE_SNAPSHOTS_READINESS_TOLERANCE")
+                .takes_value(true)
+                .value_name("F64")
+                .help("Multiple of the snapshot frequency the last successful snapshot may be old for /readyz to report ready"),
+        )
+        .arg(
+            Arg::with_name("archive-compression")
+                .long("archive-compression")
+                .env("TEZEDGE_SNAPSHOTS_ARCHIVE_COMPRESSION")
+                .takes_value(true)
+                .value_name("COMPRESSION[:LEVEL]")
+                .help("Compression and level of the archive snapshots, e.g. xz:9, overrides the compression"),
+        )
+        .arg(
+            Arg::with_name("full-compression")
+                .long("full-compression")
+                .env("TEZEDGE_SNAPSHOTS_FULL_COMPRESSION")
+                .takes_value(true)
+                .value_name("COMPRESSION[:LEVEL]")
+                .help("Compression and level of the full snapshots, e.g. gzip:6, overrides the compression"),
+        )
+        .arg(
+            Arg::with_name("stop-confirm-timeout")
+                .long("stop-confirm-timeout")
+                .env("TEZEDGE_SNAPSHOTS_STOP_CONFIRM_TIMEOUT")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time the stopped node container has to be confirmed not running before the snapshot reads the database"),
+        )
+        .arg(
+            Arg::with_name("post-stop-delay")
+                .long("post-stop-delay")
+                .env("TEZEDGE_SNAPSHOTS_POST_STOP_DELAY")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time to wait after the node container is confirmed stopped before the snapshot reads the database"),
+        )
+        .arg(
+            Arg::with_name("host-data-path")
+                .long("host-data-path")
+                .env("TEZEDGE_SNAPSHOTS_HOST_DATA_PATH")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Path of the tezedge database directory on the docker host, bind mounted into the full snapshot container"),
+        )
+        .arg(
+            Arg::with_name("host-snapshots-path")
+                .long("host-snapshots-path")
+                .env("TEZEDGE_SNAPSHOTS_HOST_SNAPSHOTS_PATH")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Path of the snapshots target directory on the docker host, bind mounted into the helper containers"),
+        )
+        .arg(
+            Arg::with_name("compression-time-budget")
+                .long("compression-time-budget")
+                .env("TEZEDGE_SNAPSHOTS_COMPRESSION_TIME_BUDGET")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Time the compression of an archive should fit in, lowering the compression level when a sample estimates it would not"),
+        )
+        .arg(
+            Arg::with_name("compression-nice")
+                .long("compression-nice")
+                .env("TEZEDGE_SNAPSHOTS_COMPRESSION_NICE")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("NICE")
+                .help("Niceness from -20 to 19 the archives are compressed with, e.g. 19 to yield the CPU to the node (Linux only)"),
+        )
+        .arg(
+            Arg::with_name("external-compressor")
+                .long("external-compressor")
+                .env("TEZEDGE_SNAPSHOTS_EXTERNAL_COMPRESSOR")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .conflicts_with("validate-snapshot")
+                .help("Shell command the uncompressed tar stream is piped to, its output is written to the archive, e.g. \"zstd --long -T0\""),
+        )
+        .arg(
+            Arg::with_name("external-compressor-extension")
+                .long("external-compressor-extension")
+                .env("TEZEDGE_SNAPSHOTS_EXTERNAL_COMPRESSOR_EXTENSION")
+                .takes_value(true)
+                .value_name("EXTENSION")
+                .help("Extension of the archives written by the external compressor, e.g. tar.zst. Defaults to tar"),
+        )
+        .arg(
+            Arg::with_name("context-include")
+                .long("context-include")
+                .env("TEZEDGE_SNAPSHOTS_CONTEXT_INCLUDE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("GLOB")
+                .help("Limit the context directory in the archives to the paths below it matching the glob, e.g. \"index/**\", can be repeated"),
+        )
+        .arg(
+            Arg::with_name("min-interval")
+                .long("min-interval")
+                .env("TEZEDGE_SNAPSHOTS_MIN_INTERVAL")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Minimal time between the starts of any two snapshots, regardless of the frequency"),
+        )
+        .arg(
+            Arg::with_name("cleanup-containers-on-start")
+                .long("cleanup-containers-on-start")
+                .takes_value(false)
+                .help("Remove the stopped full snapshot and validation containers left behind by failed snapshots at startup"),
+        )
+        .arg(
+            Arg::with_name("docker-api-version")
+                .long("docker-api-version")
+                .env("TEZEDGE_SNAPSHOTS_DOCKER_API_VERSION")
+                .takes_value(true)
+                .value_name("MAJOR.MINOR")
+                .help("Docker API version to use, e.g. 1.39, for daemons older than the client default"),
+        )
+        .arg(
+            Arg::with_name("instance-id")
+                .long("instance-id")
+                .env("TEZEDGE_SNAPSHOTS_INSTANCE_ID")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("The identifier of this instance attached to the logs and metrics, defaults to the hostname"),
+        )
+        .arg(
+            Arg::with_name("full-snapshot-image")
+                .long("full-snapshot-image")
+                .env("TEZEDGE_SNAPSHOTS_FULL_SNAPSHOT_IMAGE")
+                .takes_value(true)
+                .value_name("STRING")
+                .help("The name of the tezedge image to use for the full snapshots"),
+        )
+        .arg(
+            Arg::with_name("full-snapshot-timeout")
+                .long("full-snapshot-timeout")
+                .env("TEZEDGE_SNAPSHOTS_FULL_SNAPSHOT_TIMEOUT")
+                .takes_value(true)
+                .value_name("U64")
+                .help("The time in seconds the full snapshotting container has to finish the export, it is removed after"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .env("TEZEDGE_SNAPSHOTS_LOG_LEVEL")
+                .takes_value(true)
+                .value_name("SLOG LEVEL")
+                .possible_values(&["critical", "error", "warn", "info", "debug", "trace"])
+                .help("Set logging level"),
+        )
+        .subcommand(
+            SubCommand::with_name("prune")
+                .about("Applies the snapshot retention to the existing snapshots right away and exits")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("Only list the snapshots that would be removed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("version")
+                .about("Prints the versions of the tool, the node, the docker daemon and the full snapshot image digest"),
+        )
+        .subcommand(
+            SubCommand::with_name("estimate")
+                .about("Prints the estimated size of a snapshot of the database directories without stopping the node"),
+        )
+        .subcommand(
+            SubCommand::with_name("test-rpc")
+                .about("Fetches the head of the node from the configured url, prints its hash and level and exits"),
+        )
+        .subcommand(
+            SubCommand::with_name("cleanup-containers")
+                .about("Removes the stopped full snapshot and validation containers left behind by failed snapshots and exits")
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("Only list the containers that would be removed"),
+                ),
+        );
+
+    app
+}
+
+impl TezedgeSnapshotEnvironment {
+    /// The settings of the snapshotting passed to the node controller
+    pub fn snapshot_settings(&self) -> SnapshotSettings {
+        SnapshotSettings {
+            full_snapshot_timeout: self.full_snapshot_timeout,
+            compression: self.compression.clone(),
+            format: self.format.clone(),
+            min_free_space: self.min_free_space,
+            allow_same_head: self.allow_same_head,
+            scratch_directory: self.scratch_directory.clone(),
+            post_restart_grace: self.post_restart_grace,
+            compression_threads: self.compression_threads,
+            mirror_directory: self.mirror_directory.clone(),
+            mirror_capacity: self.mirror_capacity,
+            warm_standby_dir: self.warm_standby_dir.clone(),
+            name_suffix: self.name_suffix.clone(),
+            validate_snapshot: self.validate_snapshot,
+            validation_rpc_port: self.validation_rpc_port,
+            validation_timeout: self.validation_timeout,
+            compression_buffer_size: self.compression_buffer_size,
+            rpc_poll_interval: self.rpc_poll_interval,
+            name_timestamp_format: self.name_timestamp_format.clone(),
+            naming_scheme: self.naming_scheme.clone(),
+            checksum_algorithm: self.checksum_algorithm.clone(),
+            max_total_size: self.max_total_size,
+            prefer_ipv6: self.prefer_ipv6,
+            resolve_overrides: self.resolve_overrides.clone(),
+            container_name_suffix: self.container_name_suffix.clone(),
+            monitoring_container_name_suffix: self.monitoring_container_name_suffix.clone(),
+            label: self.label.clone(),
+            label_in_name: self.label_in_name,
+            fsync: self.fsync,
+            chown: self.chown,
+            node_layout: self.node_layout.clone(),
+            watchdog_timeout: self.watchdog_timeout,
+            unreachable_grace: self.unreachable_grace,
+            no_roll: self.no_roll,
+            rpc_ca_cert: self.rpc_ca_cert.clone(),
+            rpc_insecure: self.rpc_insecure,
+            skip_lock_removal: self.skip_lock_removal,
+            strict_lock: self.strict_lock,
+            split_size: self.split_size,
+            recovery_timeout: self.recovery_timeout,
+            output_pipe: self.output_pipe.clone(),
+            restart_cooldown: self.restart_cooldown,
+            restart_failure_threshold: self.restart_failure_threshold,
+            clean_bootstrap_before_full: self.clean_bootstrap_before_full,
+            lock_release_timeout: self.lock_release_timeout,
+            additional_target_directories: self.additional_target_directories.clone(),
+            docker_timeout: self.docker_timeout,
+            docker_retries: self.docker_retries,
+            checkpoint_rpc_path: self.checkpoint_rpc_path.clone(),
+            archive_compression: self.archive_compression.clone(),
+            full_compression: self.full_compression.clone(),
+            stop_confirm_timeout: self.stop_confirm_timeout,
+            post_stop_delay: self.post_stop_delay,
+            docker_api_version: self.docker_api_version,
+            host_data_path: self.host_data_path.clone(),
+            host_snapshots_path: self.host_snapshots_path.clone(),
+            compression_time_budget: self.compression_time_budget,
+            compression_nice: self.compression_nice,
+            external_compressor: self.external_compressor.clone(),
+            external_compressor_extension: self.external_compressor_extension.clone(),
+            context_include: self.context_include.clone(),
+            torrent_trackers: self.torrent_trackers.clone(),
+            torrent_web_seeds: self.torrent_web_seeds.clone(),
+            min_interval: self.min_interval,
+        }
+    }
+
+    /// A copy safe to log, the webhook url may embed a token so only its origin is kept
+    pub fn redacted(&self) -> Self {
+        let mut redacted = self.clone();
+        let _ = redacted.tezedge_node_url.set_password(None);
+        if let Some(webhook_url) = &mut redacted.webhook_url {
+            webhook_url.set_path("/redacted");
+            webhook_url.set_query(None);
+            webhook_url.set_fragment(None);
+            let _ = webhook_url.set_username("");
+            let _ = webhook_url.set_password(None);
+        }
+        if let Some(event_sink) = &mut redacted.event_sink {
+            let _ = event_sink.set_username("");
+            let _ = event_sink.set_password(None);
+        }
+        redacted
+    }
+
+    pub fn from_args() -> Self {
+        let app = tezedge_snapshots_app();
+        let args = app.clone().get_matches();
+
+        Self {
+            log_level: args
+                .value_of("log-level")
+                .unwrap_or("info")
+                .parse::<slog::Level>()
+                .expect("Was expecting one value from slog::Level"),
+
+            check_interval: args
+                .value_of("check-interval")
+                .unwrap_or("5")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+
+            tezedge_node_url: args
+                .value_of("tezedge-node-url")
+                .unwrap_or("http://localhost:18732")
+                .parse::<Url>()
+                .expect("Was expecting a valid url"),
+            node_container_name: args
+                .value_of("node-container-name")
+                .unwrap_or("tezedge-node")
+                .to_string(),
+            monitoring_container_name: args
+                .value_of("monitoring-container-name")
+                .unwrap_or("tezedge-node-monitoring")
+                .to_string(),
+            network: args.value_of("network").unwrap_or("network").to_string(),
+            snapshots_target_directory: args
+                .value_of("snapshots-target-directory")
+                .unwrap_or("/tmp/snapshots")
+                .parse::<PathBuf>()
+                .expect("The provided path is invalid"),
+            tezedge_database_directory: args
+                .value_of("tezedge-database-directory")
+                .unwrap_or("/tmp/tezedge")
+                .parse::<PathBuf>()
+                .expect("The provided path is invalid"),
+            snapshot_capacity: parse_snapshot_capacity(args.value_of("snapshot-capacity").unwrap_or("7")),
             snapshot_frequency: args
                 .value_of("snapshot-frequency")
                 .unwrap_or("86400")
@@ -281,11 +1534,375 @@ impl TezedgeSnapshotEnvironment {
                 .value_of("full-snapshot-image")
                 .unwrap_or("tezedge/tezedge:latest")
                 .to_string(),
+            full_snapshot_timeout: args
+                .value_of("full-snapshot-timeout")
+                .unwrap_or("43200")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
             context_type: args
                 .value_of("context-type")
                 .unwrap_or("irmin")
                 .parse::<ContextType>()
-                .expect("Expected values archive, full or all"),
+                .expect("Expected values irmin or tezedge"),
+            compression: args
+                .value_of("compression")
+                .unwrap_or("gzip")
+                .parse::<CompressionType>()
+                .expect("Expected values gzip or xz"),
+            format: args
+                .value_of("format")
+                .unwrap_or("tar")
+                .parse::<SnapshotFormat>()
+                .expect("Expected values tar, dir or members"),
+            min_free_space: args
+                .value_of("min-free-space")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .expect("Expected u64 value of bytes"),
+            allow_same_head: is_flag_set(&args, "allow-same-head"),
+            scratch_directory: args
+                .value_of("scratch-dir")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            post_restart_grace: args
+                .value_of("post-restart-grace")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            compression_threads: resolve_compression_threads(
+                args.value_of("compression-threads").unwrap_or("1"),
+            ),
+            mirror_directory: args
+                .value_of("mirror-directory")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            mirror_capacity: args
+                .value_of("mirror-capacity")
+                .map(|c| {
+                    let capacity = c.parse::<usize>().expect("Expected usize value");
+                    assert!(capacity >= 1, "Expected a mirror capacity of at least 1, got 0");
+                    capacity
+                }),
+            warm_standby_dir: args
+                .value_of("warm-standby-dir")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            check_rpc_version: is_flag_set(&args, "check-rpc-version"),
+            name_suffix: args.value_of("name-suffix").map(|s| s.to_string()),
+            validate_snapshot: is_flag_set(&args, "validate-snapshot"),
+            validation_rpc_port: args
+                .value_of("validation-rpc-port")
+                .unwrap_or("18799")
+                .parse::<u16>()
+                .expect("Expected u16 value of a port"),
+            validation_timeout: args
+                .value_of("validation-timeout")
+                .unwrap_or("1800")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            api_address: args
+                .value_of("api-address")
+                .map(|a| a.parse::<SocketAddr>().expect("Was expecting a valid socket address")),
+            compression_buffer_size: args
+                .value_of("compression-buffer-size")
+                .unwrap_or("1048576")
+                .parse::<usize>()
+                .expect("Expected usize value of bytes"),
+            rpc_poll_interval: args
+                .value_of("rpc-poll-interval")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            manage_node_lifecycle: is_flag_set(&args, "manage-node-lifecycle"),
+            allow_missing_container: is_flag_set(&args, "allow-missing-container"),
+            webhook_url: args
+                .value_of("webhook-url")
+                .map(|u| u.to_string())
+                .or_else(|| args.value_of("webhook-url-file").map(read_secret_file))
+                .map(|u| u.parse::<Url>().expect("Was expecting a valid url")),
+            event_sink: args.value_of("event-sink").map(|u| {
+                let url = u.parse::<Url>().expect("Was expecting a valid url");
+                assert_eq!(url.scheme(), "nats", "Only nats:// event sinks are supported");
+                url
+            }),
+            event_subject: args
+                .value_of("event-subject")
+                .unwrap_or("tezedge.snapshots")
+                .to_string(),
+            torrent_trackers: args
+                .values_of("torrent-tracker")
+                .map(|values| values.map(|u| u.parse::<Url>().expect("Was expecting a valid url")).collect())
+                .unwrap_or_default(),
+            torrent_web_seeds: args
+                .values_of("torrent-web-seed")
+                .map(|values| values.map(|u| u.parse::<Url>().expect("Was expecting a valid url")).collect())
+                .unwrap_or_default(),
+            failure_escalation_threshold: args
+                .value_of("failure-escalation-threshold")
+                .unwrap_or("3")
+                .parse::<u32>()
+                .expect("Expected u32 value"),
+            name_timestamp_format: args
+                .value_of("name-timestamp-format")
+                .unwrap_or("%Y%m%d-%H%M%S")
+                .to_string(),
+            naming_scheme: args
+                .value_of("naming-scheme")
+                .unwrap_or("tezedge")
+                .parse::<NamingScheme>()
+                .expect("Expected values tezedge or octez"),
+            checksum_algorithm: args.value_of("checksum-algorithm").map(|a| {
+                a.parse::<ChecksumAlgorithm>()
+                    .expect("Expected values sha256 or blake3")
+            }),
+            max_total_size: args
+                .value_of("max-total-size")
+                .map(|s| s.parse::<u64>().expect("Expected u64 value of bytes")),
+            prefer_ipv6: is_flag_set(&args, "prefer-ipv6"),
+            resolve_overrides: args
+                .values_of("resolve")
+                .map(|values| values.map(parse_resolve_override).collect())
+                .unwrap_or_default(),
+            container_name_suffix: args
+                .value_of("container-name-suffix")
+                .unwrap_or("-{context}-{network}")
+                .to_string(),
+            monitoring_container_name_suffix: args.value_of("monitoring-container-name-suffix").map(|s| s.to_string()),
+            label: args.value_of("label").map(|s| s.to_string()),
+            label_in_name: is_flag_set(&args, "label-in-name"),
+            fsync: !is_flag_set(&args, "no-fsync"),
+            chown: args.value_of("chown").map(|owner| {
+                let (uid, gid) = owner.split_once(':').expect("Expected a UID:GID value");
+                (
+                    uid.parse::<u32>().expect("Expected u32 value of a uid"),
+                    gid.parse::<u32>().expect("Expected u32 value of a gid"),
+                )
+            }),
+            node_layout: args
+                .value_of("node-layout")
+                .unwrap_or("v1")
+                .parse::<NodeLayout>()
+                .expect("Expected value v1"),
+            watchdog_timeout: args
+                .value_of("watchdog-timeout")
+                .map(|s| s.parse::<u64>().expect("Expected u64 value of seconds")),
+            unreachable_grace: args
+                .value_of("unreachable-grace")
+                .unwrap_or("3600")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            no_roll: is_flag_set(&args, "no-roll"),
+            rpc_ca_cert: args
+                .value_of("rpc-ca-cert")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            rpc_insecure: is_flag_set(&args, "rpc-insecure"),
+            skip_lock_removal: is_flag_set(&args, "skip-lock-removal"),
+            strict_lock: is_flag_set(&args, "strict-lock"),
+            split_size: args
+                .value_of("split-size")
+                .map(|s| s.parse::<u64>().expect("Expected u64 value of bytes")),
+            recovery_timeout: args
+                .value_of("recovery-timeout")
+                .unwrap_or("600")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            resilient: is_flag_set(&args, "resilient"),
+            resilient_backoff: args
+                .value_of("resilient-backoff")
+                .unwrap_or("300")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            output_pipe: args
+                .value_of("output-pipe")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            restart_cooldown: args
+                .value_of("restart-cooldown")
+                .unwrap_or("30")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            restart_failure_threshold: args
+                .value_of("restart-failure-threshold")
+                .unwrap_or("3")
+                .parse::<u32>()
+                .expect("Expected u32 value"),
+            clean_bootstrap_before_full: is_flag_set(&args, "clean-bootstrap-before-full"),
+            lock_release_timeout: args
+                .value_of("lock-release-timeout")
+                .unwrap_or("30")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            additional_target_directories: args
+                .values_of("additional-target-directory")
+                .map(|values| {
+                    values
+                        .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid"))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            docker_timeout: args
+                .value_of("docker-timeout")
+                .unwrap_or("120")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            docker_retries: args
+                .value_of("docker-retries")
+                .unwrap_or("2")
+                .parse::<u32>()
+                .expect("Expected u32 value"),
+            checkpoint_rpc_path: args.value_of("checkpoint-rpc-path").map(|s| s.to_string()),
+            output: args
+                .value_of("output")
+                .unwrap_or("text")
+                .parse::<OutputFormat>()
+                .expect("Expected values text or json"),
+            readiness_tolerance: args
+                .value_of("readiness-tolerance")
+                .unwrap_or("2")
+                .parse::<f64>()
+                .expect("Expected f64 value"),
+            archive_compression: args.value_of("archive-compression").map(|c| {
+                c.parse::<CompressionSetting>()
+                    .expect("Expected values gzip or xz with an optional level from 0 to 9, e.g. xz:9")
+            }),
+            full_compression: args.value_of("full-compression").map(|c| {
+                c.parse::<CompressionSetting>()
+                    .expect("Expected values gzip or xz with an optional level from 0 to 9, e.g. gzip:6")
+            }),
+            stop_confirm_timeout: args
+                .value_of("stop-confirm-timeout")
+                .unwrap_or("60")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            post_stop_delay: args
+                .value_of("post-stop-delay")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            host_data_path: args
+                .value_of("host-data-path")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            host_snapshots_path: args
+                .value_of("host-snapshots-path")
+                .map(|p| p.parse::<PathBuf>().expect("The provided path is invalid")),
+            compression_time_budget: args
+                .value_of("compression-time-budget")
+                .map(|s| s.parse::<u64>().expect("Expected u64 value of seconds")),
+            compression_nice: args.value_of("compression-nice").map(|s| {
+                let nice = s.parse::<i32>().expect("Expected i32 value");
+                assert!((-20..=19).contains(&nice), "Expected a niceness from -20 to 19");
+                nice
+            }),
+            external_compressor: args.value_of("external-compressor").map(|s| s.to_string()),
+            external_compressor_extension: args
+                .value_of("external-compressor-extension")
+                .unwrap_or("tar")
+                .trim_start_matches('.')
+                .to_string(),
+            context_include: args
+                .values_of("context-include")
+                .map(|values| values.map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+            min_interval: args
+                .value_of("min-interval")
+                .unwrap_or("0")
+                .parse::<u64>()
+                .expect("Expected u64 value of seconds"),
+            cleanup_containers_on_start: is_flag_set(&args, "cleanup-containers-on-start"),
+            docker_api_version: args.value_of("docker-api-version").map(parse_docker_api_version),
+            instance_id: args
+                .value_of("instance-id")
+                .map(|s| s.to_string())
+                .unwrap_or_else(hostname),
+            command: match args.subcommand() {
+                ("prune", Some(prune_args)) => Command::Prune {
+                    dry_run: prune_args.is_present("dry-run"),
+                },
+                ("version", Some(_)) => Command::Version,
+                ("estimate", Some(_)) => Command::Estimate,
+                ("test-rpc", Some(_)) => Command::TestRpc,
+                ("cleanup-containers", Some(cleanup_args)) => Command::CleanupContainers {
+                    dry_run: cleanup_args.is_present("dry-run"),
+                },
+                _ => Command::Run,
+            },
+        }
+    }
+}
+
+/// Whether the flag is given on the command line or its environment variable is set to true or 1
+fn is_flag_set(args: &ArgMatches, name: &str) -> bool {
+    let variable = format!("TEZEDGE_SNAPSHOTS_{}", name.to_uppercase().replace('-', "_"));
+    args.is_present(name) || matches!(env::var(variable).as_deref(), Ok("true") | Ok("1"))
+}
+
+/// The hostname of the machine, the container id when running in docker
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// Parses a capacity for all types optionally followed by per type overrides, the unlisted types keep 7
+fn parse_snapshot_capacity(value: &str) -> SnapshotCapacity {
+    let mut capacity = SnapshotCapacity { archive: 7, full: 7 };
+    for part in value.split(',') {
+        let parse = |count: &str| {
+            let count = count.parse::<usize>().expect("Expected usize value");
+            // the snapshot just taken is always kept, a capacity of 0 can not be honoured
+            assert!(count >= 1, "Expected a snapshot capacity of at least 1, got 0");
+            count
+        };
+        match part.split_once('=') {
+            None => {
+                let count = parse(part);
+                capacity = SnapshotCapacity { archive: count, full: count };
+            }
+            Some(("archive", count)) => capacity.archive = parse(count),
+            Some(("full", count)) => capacity.full = parse(count),
+            Some((kind, _)) => panic!("Expected snapshot types archive or full, got {}", kind),
         }
     }
+    capacity
+}
+
+/// Parses a MAJOR.MINOR docker API version, e.g. 1.39
+fn parse_docker_api_version(value: &str) -> (usize, usize) {
+    let parse = |number: &str| number.parse::<usize>().expect("Expected a MAJOR.MINOR version, e.g. 1.39");
+    let (major, minor) = value
+        .trim_start_matches('v')
+        .split_once('.')
+        .expect("Expected a MAJOR.MINOR version, e.g. 1.39");
+    (parse(major), parse(minor))
+}
+
+/// Parses a HOST=IP override, the port is always taken from the node url
+fn parse_resolve_override(value: &str) -> (String, SocketAddr) {
+    let (host, address) = value.split_once('=').expect("Expected a HOST=IP value");
+    let address = address
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .expect("Was expecting a valid IP address");
+    (host.to_string(), SocketAddr::new(address, 0))
+}
+
+/// Reads a secret mounted as a file (e.g. a docker or kubernetes secret), surrounding whitespace is trimmed
+fn read_secret_file(path: &str) -> String {
+    std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read the secret file {}: {}", path, e))
+        .trim()
+        .to_string()
+}
+
+/// Resolves the compression thread count clamped to the available cores, auto keeps one core free for the node
+fn resolve_compression_threads(value: &str) -> u32 {
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1);
+
+    let threads = if value == "auto" {
+        cores.saturating_sub(1)
+    } else {
+        value.parse::<u32>().expect("Expected u32 value or auto")
+    };
+
+    threads.clamp(1, cores)
 }
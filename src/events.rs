@@ -0,0 +1,148 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use serde::Serialize;
+use slog::{warn, Logger};
+use url::Url;
+
+/// Snapshot lifecycle event published to the event sink
+#[derive(Debug, Serialize)]
+struct SnapshotEvent<'a> {
+    event: &'a str,
+    instance: &'a str,
+    timestamp: String,
+    #[serde(flatten)]
+    metadata: serde_json::Value,
+}
+
+/// Publishes snapshot lifecycle events to a NATS subject, a no-op when no sink is configured
+#[derive(Clone)]
+pub struct EventSink {
+    url: Option<Url>,
+    subject: String,
+    instance_id: String,
+    log: Logger,
+}
+
+impl EventSink {
+    pub fn new(url: Option<Url>, subject: String, instance_id: String, log: Logger) -> Self {
+        if url.is_some() && cfg!(not(feature = "event-sink")) {
+            warn!(log, "Built without the event-sink feature, the snapshot events are not published");
+        }
+        Self {
+            url,
+            subject,
+            instance_id,
+            log,
+        }
+    }
+
+    /// Publishes the event with its metadata merged into the top level, a failed delivery only warns
+    pub async fn publish(&self, event: &str, metadata: serde_json::Value) {
+        let url = match &self.url {
+            Some(url) => url,
+            None => return,
+        };
+
+        let event_body = SnapshotEvent {
+            event,
+            instance: &self.instance_id,
+            timestamp: Utc::now().to_rfc3339(),
+            metadata,
+        };
+        let payload = match serde_json::to_vec(&event_body) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(self.log, "Failed to serialize the {} event: {}", event, e);
+                return;
+            }
+        };
+
+        if let Err(e) = nats::publish(url, &self.subject, &payload).await {
+            warn!(self.log, "Failed to publish the {} event: {}", event, e);
+        }
+    }
+}
+
+#[cfg(feature = "event-sink")]
+mod nats {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+        time,
+    };
+    use url::Url;
+
+    /// Time a whole publish may take, the events must not hold up the snapshotting
+    const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Publishes the payload over a short lived connection speaking the core NATS text protocol, the trailing
+    /// PING makes the server confirm it processed the message
+    pub async fn publish(url: &Url, subject: &str, payload: &[u8]) -> Result<(), std::io::Error> {
+        match time::timeout(PUBLISH_TIMEOUT, publish_unbounded(url, subject, payload)).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "NATS publish timed out")),
+        }
+    }
+
+    async fn publish_unbounded(url: &Url, subject: &str, payload: &[u8]) -> Result<(), std::io::Error> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "The event sink url has no host"))?;
+        let stream = TcpStream::connect((host, url.port().unwrap_or(4222))).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        // the server greets with its INFO
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+
+        let mut connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "tezedge-snapshots",
+        });
+        match (url.username(), url.password()) {
+            ("", _) => (),
+            (token, None) => connect["auth_token"] = token.into(),
+            (user, Some(pass)) => {
+                connect["user"] = user.into();
+                connect["pass"] = pass.into();
+            }
+        }
+
+        let mut message = format!("CONNECT {}\r\nPUB {} {}\r\n", connect, subject, payload.len()).into_bytes();
+        message.extend_from_slice(payload);
+        message.extend_from_slice(b"\r\nPING\r\n");
+        writer.write_all(&message).await?;
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "The NATS server closed the connection",
+                ));
+            }
+            match line.trim_end() {
+                "PONG" => return Ok(()),
+                error if error.starts_with("-ERR") => {
+                    return Err(std::io::Error::other(error.to_string()));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "event-sink"))]
+mod nats {
+    use url::Url;
+
+    pub async fn publish(_url: &Url, _subject: &str, _payload: &[u8]) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+/// Whether the slash separated path matches the glob pattern. `*` matches any characters and `?` a single one within
+/// a path segment, a `**` segment matches any number of segments
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|segment| !segment.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    matches_segments(&pattern, &path)
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skipped| matches_segments(rest, &path[skipped..])),
+        Some((segment_pattern, rest)) => match path.split_first() {
+            Some((segment, path_rest)) => {
+                let segment_pattern: Vec<char> = segment_pattern.chars().collect();
+                let segment: Vec<char> = segment.chars().collect();
+                matches_segment(&segment_pattern, &segment) && matches_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_segment(pattern: &[char], segment: &[char]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some(('*', rest)) => (0..=segment.len()).any(|skipped| matches_segment(rest, &segment[skipped..])),
+        Some(('?', rest)) => !segment.is_empty() && matches_segment(rest, &segment[1..]),
+        Some((c, rest)) => segment.first() == Some(c) && matches_segment(rest, &segment[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn literal() {
+        assert!(matches("index/store.pack", "index/store.pack"));
+        assert!(!matches("index/store.pack", "index/store.dict"));
+        assert!(!matches("index", "index/store.pack"));
+    }
+
+    #[test]
+    fn star() {
+        assert!(matches("*.pack", "store.pack"));
+        assert!(matches("store.*", "store.pack"));
+        assert!(matches("st*ck", "store.pack"));
+        assert!(matches("store*", "store"));
+        assert!(!matches("*.pack", "store.dict"));
+    }
+
+    #[test]
+    fn star_within_a_segment() {
+        assert!(!matches("*", "index/store.pack"));
+        assert!(!matches("index*pack", "index/store.pack"));
+        assert!(matches("*/*.pack", "index/store.pack"));
+    }
+
+    #[test]
+    fn star_backtracking() {
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(!matches("a*b*c", "aXcYc"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("store.pac?", "store.pack"));
+        assert!(matches("?tore.pack", "store.pack"));
+        assert!(!matches("store.pack?", "store.pack"));
+        assert!(!matches("index?store.pack", "index/store.pack"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(matches("**/store.pack", "store.pack"));
+        assert!(matches("**/store.pack", "index/nested/store.pack"));
+        assert!(matches("index/**", "index/nested/store.pack"));
+        assert!(!matches("**/store.pack", "index/store.dict"));
+    }
+
+    #[test]
+    fn empty_pattern() {
+        assert!(matches("", ""));
+        assert!(!matches("", "store.pack"));
+    }
+}
@@ -4,21 +4,44 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use slog::{error, info, warn, Drain, Level, Logger};
-use tokio::{signal, time};
+use slog::{crit, error, info, warn, Drain, Level, Logger};
+use tokio::{
+    signal,
+    sync::mpsc::{self, error::TrySendError},
+    time,
+};
 
+pub mod api;
+pub mod checksum;
 pub mod configuration;
+pub mod events;
+pub mod glob;
+pub mod manifest;
+pub mod metrics;
 pub mod node;
+pub mod output;
+pub mod state;
+pub mod torrent;
+pub mod webhook;
 
-use crate::configuration::TezedgeSnapshotEnvironment;
+use crate::api::SnapshotRequest;
+use crate::configuration::{Command, TezedgeSnapshotEnvironment};
+use crate::events::EventSink;
 use crate::node::{TezedgeNodeController, TezedgeNodeControllerError};
+use crate::output::{CleanupContainersOutput, EstimateOutput, MemberSize, PruneOutput, TestRpcOutput, VersionOutput};
+use crate::webhook::Webhook;
 
 #[tokio::main]
 async fn main() {
     let env = TezedgeSnapshotEnvironment::from_args();
 
+    // create an slog logger
+    let log = create_logger(env.log_level, env.instance_id.clone());
+
+    info!(log, "Effective configuration: {:?}", env.redacted());
+
+    let settings = env.snapshot_settings();
     let TezedgeSnapshotEnvironment {
-        log_level,
         tezedge_node_url,
         check_interval,
         node_container_name,
@@ -31,10 +54,34 @@ async fn main() {
         snapshot_type,
         full_snapshot_image,
         context_type,
+        compression,
+        compression_threads,
+        command,
+        check_rpc_version,
+        api_address,
+        manage_node_lifecycle,
+        allow_missing_container,
+        webhook_url,
+        event_sink,
+        event_subject,
+        torrent_trackers,
+        torrent_web_seeds,
+        failure_escalation_threshold,
+        resilient,
+        resilient_backoff,
+        additional_target_directories,
+        output,
+        readiness_tolerance,
+        cleanup_containers_on_start,
+        instance_id,
+        ..
     } = env;
 
-    // create an slog logger
-    let log = create_logger(log_level);
+    let webhook = Webhook::new(webhook_url, log.clone());
+    let events = EventSink::new(event_sink, event_subject, instance_id.clone(), log.clone());
+    if (!torrent_trackers.is_empty() || !torrent_web_seeds.is_empty()) && cfg!(not(feature = "torrent")) {
+        warn!(log, "Built without the torrent feature, no .torrent files are written");
+    }
 
     let mut node = TezedgeNodeController::new(
         tezedge_node_url,
@@ -42,31 +89,287 @@ async fn main() {
         monitoring_container_name,
         network,
         tezedge_database_directory,
-        snapshots_target_directory,
+        snapshots_target_directory.clone(),
         full_snapshot_image,
         context_type,
+        settings,
+        webhook.clone(),
+        events,
+        instance_id,
         log.clone(),
     );
 
+    if let Command::Version = command {
+        let version = VersionOutput {
+            tool: env!("CARGO_PKG_VERSION").to_string(),
+            node: node
+                .get_version()
+                .await
+                .map(|version| {
+                    format!(
+                        "{}.{} ({}) on chain {}",
+                        version.version.major,
+                        version.version.minor,
+                        version.version.additional_info,
+                        version.network_version.chain_name
+                    )
+                })
+                .into(),
+            docker: node.docker_version().await.into(),
+            full_snapshot_image: node.full_snapshot_image_digest().await.into(),
+        };
+        output::print(&version, &output);
+        return;
+    }
+
+    if let Command::Estimate = command {
+        match node.estimate() {
+            Ok(sizes) => {
+                let total: u64 = sizes.iter().map(|(_, size)| size).sum();
+                let estimate = EstimateOutput {
+                    members: sizes
+                        .into_iter()
+                        .map(|(name, size_bytes)| MemberSize { name, size_bytes })
+                        .collect(),
+                    uncompressed_size_bytes: total,
+                    compression: compression.to_string(),
+                    estimated_compressed_size_bytes: (total as f64 * compression.estimated_ratio()) as u64,
+                };
+                output::print(&estimate, &output);
+            }
+            Err(e) => {
+                error!(log, "Estimate failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Command::Prune { dry_run } = command {
+        match node.prune(&snapshot_capacity, dry_run) {
+            Ok(pruned) => {
+                let prune = PruneOutput {
+                    dry_run,
+                    snapshots: pruned
+                        .iter()
+                        .map(|snapshot| snapshot.to_string_lossy().to_string())
+                        .collect(),
+                };
+                output::print(&prune, &output);
+            }
+            Err(e) => {
+                error!(log, "Prune failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Command::TestRpc = command {
+        // the error is part of the output, the logger may not flush before the exit
+        let test_rpc = match node.get_head().await {
+            Ok(head) => TestRpcOutput {
+                reachable: true,
+                block_hash: Some(head.hash),
+                level: Some(head.level),
+                error: None,
+            },
+            Err(e) => TestRpcOutput {
+                reachable: false,
+                block_hash: None,
+                level: None,
+                error: Some(e.to_string()),
+            },
+        };
+        output::print(&test_rpc, &output);
+        if !test_rpc.reachable {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::CleanupContainers { dry_run } = command {
+        match node.cleanup_containers(dry_run).await {
+            Ok(containers) => output::print(&CleanupContainersOutput { dry_run, containers }, &output),
+            Err(e) => {
+                error!(log, "Cleaning up the containers failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    info!(log, "Using {} compression thread(s)", compression_threads);
+
+    if check_rpc_version {
+        match node.get_version().await {
+            Ok(version) => info!(
+                log,
+                "Node RPC version {}.{} ({}) on chain {}",
+                version.version.major,
+                version.version.minor,
+                version.version.additional_info,
+                version.network_version.chain_name
+            ),
+            Err(e) => warn!(log, "The node url does not look like a tezedge/octez RPC: {}", e),
+        }
+    }
+
+    // a misnamed container would otherwise only surface once the first snapshot fails to stop it
+    match node.resolve_containers().await {
+        Err(e @ TezedgeNodeControllerError::ContainerNotFound(_)) if allow_missing_container => {
+            warn!(log, "{}, continuing as --allow-missing-container is set", e)
+        }
+        Err(e @ TezedgeNodeControllerError::ContainerNotFound(_)) => {
+            error!(log, "{}", e);
+            std::process::exit(1);
+        }
+        Err(e) => warn!(log, "Failed to list the docker containers: {}", e),
+        Ok(()) => (),
+    }
+
+    // a node container stopped at startup would otherwise keep the node unreachable forever
+    match node.check_node_running(manage_node_lifecycle).await {
+        Err(TezedgeNodeControllerError::ContainerNotFound(_)) if allow_missing_container => (),
+        Err(e @ TezedgeNodeControllerError::ContainerNotFound(_)) => {
+            error!(log, "{}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            warn!(log, "Failed to check the node container state: {}", e);
+            // an API version mismatch shows up here first, log what the daemon supports
+            match node.docker_version().await {
+                Ok(docker_version) => warn!(log, "Docker daemon {}", docker_version),
+                Err(e) => warn!(log, "Failed to query the docker daemon version: {}", e),
+            }
+        }
+        Ok(()) => (),
+    }
+
+    if cleanup_containers_on_start {
+        match node.cleanup_containers(false).await {
+            Ok(containers) if !containers.is_empty() => info!(log, "Removed {} orphaned container(s)", containers.len()),
+            Ok(_) => (),
+            Err(e) => warn!(log, "Failed to clean up the orphaned containers: {}", e),
+        }
+    }
+
+    let paused = api::SharedPaused::default();
+    // every snapshot, scheduled or manual, goes through the queue to the single worker owning the controller
+    let (snapshot_queue, mut snapshot_requests) = mpsc::channel(api::SNAPSHOT_QUEUE_SIZE);
+
+    if let Some(api_address) = api_address {
+        tokio::spawn(api::serve(
+            api_address,
+            node.status(),
+            paused.clone(),
+            snapshot_queue.clone(),
+            node.state(),
+            node.health(),
+            time::Duration::from_secs_f64(snapshot_frequency as f64 * readiness_tolerance),
+            node.metrics(),
+            std::iter::once(snapshots_target_directory.clone())
+                .chain(additional_target_directories)
+                .collect(),
+            log.clone(),
+        ));
+    }
+
+    // at most one tick waits in the queue, the ticks piling up behind a long snapshot would only repeat the checks
+    let tick_pending = Arc::new(AtomicBool::new(false));
+    let scheduler_tick_pending = tick_pending.clone();
+    tokio::spawn(async move {
+        loop {
+            if !scheduler_tick_pending.swap(true, Ordering::AcqRel) {
+                match snapshot_queue.try_send(SnapshotRequest::Scheduled) {
+                    Ok(()) => (),
+                    // full of manual requests, the schedule is checked again after them
+                    Err(TrySendError::Full(_)) => scheduler_tick_pending.store(false, Ordering::Release),
+                    Err(TrySendError::Closed(_)) => break,
+                }
+            }
+            time::sleep(time::Duration::from_secs(check_interval)).await;
+        }
+    });
+
     let running = Arc::new(AtomicBool::new(true));
 
     let running_thread = running.clone();
     let thread_log = log.clone();
     let handle = tokio::spawn(async move {
-        while running_thread.load(std::sync::atomic::Ordering::Acquire) {
-            if node.can_snapshot(snapshot_frequency).await {
-                info!(thread_log, "Taking new snapshot");
-                if let Err(e) = node.take_snapshot(snapshot_capacity, &snapshot_type).await {
+        let mut consecutive_failures = 0;
+        let mut was_paused = false;
+        while let Some(request) = snapshot_requests.recv().await {
+            if !running_thread.load(std::sync::atomic::Ordering::Acquire) {
+                break;
+            }
+
+            match request {
+                SnapshotRequest::Scheduled => {
+                    tick_pending.store(false, Ordering::Release);
+                    let is_paused = paused.load(Ordering::Acquire);
+                    if is_paused != was_paused {
+                        if is_paused {
+                            info!(thread_log, "Snapshotting paused");
+                        } else {
+                            info!(thread_log, "Snapshotting resumed");
+                        }
+                        was_paused = is_paused;
+                    }
+
+                    // the node may be stopped on purpose during maintenance, the watchdog is suppressed as well
+                    if is_paused {
+                        continue;
+                    }
+                    if !node.can_snapshot(snapshot_frequency).await {
+                        node.log_duration_stats();
+                        match node.check_watchdog().await {
+                            Ok(true) => webhook.notify("watchdog", "The node was unreachable, its container was restarted").await,
+                            Ok(false) => (),
+                            Err(e) => error!(thread_log, "Watchdog failed to restart the node container: {}", e),
+                        }
+                        continue;
+                    }
+                    info!(thread_log, "Taking new snapshot");
+                }
+                SnapshotRequest::Manual => info!(thread_log, "Taking a manually requested snapshot"),
+            }
+
+            match node.take_snapshot(&snapshot_capacity, &snapshot_type).await {
+                Ok(()) => consecutive_failures = 0,
+                Err(e) => {
+                    consecutive_failures += 1;
+                    // escalate once per outage, a persistent problem is distinct from a one-off blip
+                    if consecutive_failures == failure_escalation_threshold {
+                        let message = format!("{} consecutive snapshots failed, last error: {}", consecutive_failures, e);
+                        crit!(thread_log, "{}", message);
+                        webhook.notify("escalation", &message).await;
+                    }
+
                     match e {
-                        TezedgeNodeControllerError::NodeUnreachable => warn!(thread_log, "{:?}", e),
+                        TezedgeNodeControllerError::NodeUnreachable
+                        | TezedgeNodeControllerError::InsufficientFreeSpace { .. }
+                        | TezedgeNodeControllerError::TargetNotWritable(..) => {
+                            warn!(thread_log, "{:?}", e)
+                        }
                         _ => {
                             error!(thread_log, "{:?}", e);
-                            break;
+                            if let Err(write_error) = node.write_last_error(&e) {
+                                error!(thread_log, "Failed to write last-error.json: {}", write_error);
+                            }
+                            if !resilient {
+                                break;
+                            }
+
+                            // the failed snapshot may have left the node stopped
+                            if let Err(e) = node.check_node_running(true).await {
+                                error!(thread_log, "Failed to ensure the node is running: {}", e);
+                            }
+                            warn!(thread_log, "Resilient mode - retrying in {}s", resilient_backoff);
+                            time::sleep(time::Duration::from_secs(resilient_backoff)).await;
                         }
                     }
                 }
-            } else {
-                time::sleep(time::Duration::from_secs(check_interval)).await;
             }
         }
     });
@@ -84,7 +387,7 @@ async fn main() {
 }
 
 /// Creates a slog Logger
-fn create_logger(level: Level) -> Logger {
+fn create_logger(level: Level, instance_id: String) -> Logger {
     let drain = slog_async::Async::new(
         slog_term::FullFormat::new(slog_term::TermDecorator::new().build())
             .build()
@@ -95,5 +398,5 @@ fn create_logger(level: Level) -> Logger {
     .build()
     .filter_level(level)
     .fuse();
-    Logger::root(drain, slog::o!())
+    Logger::root(drain, slog::o!("instance" => instance_id))
 }
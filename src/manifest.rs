@@ -0,0 +1,227 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Extension of the manifest sidecar written next to each snapshot
+pub const MANIFEST_EXTENSION: &str = "manifest.json";
+
+/// All sidecar extensions, sidecars are not snapshots themselves and are rolled together with their snapshot
+pub const SIDECAR_EXTENSIONS: &[&str] = &[MANIFEST_EXTENSION, "sha256", "blake3", "torrent"];
+
+/// Extension of the files and directories still being written
+pub const TEMP_EXTENSION: &str = "temp";
+
+/// Name of the pointer to the newest snapshot kept in each snapshot type directory
+pub const LATEST_FILE_NAME: &str = "latest.json";
+
+/// Pointer to the newest snapshot of a directory, for download clients not able to follow symlinks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LatestSnapshot {
+    pub name: String,
+    pub size_bytes: u64,
+    pub block_hash: String,
+    pub level: i32,
+    pub created_at: String,
+    pub parts: Option<Vec<String>>,
+    pub members: Option<Vec<String>>,
+}
+
+impl LatestSnapshot {
+    /// Writes the pointer into the directory, the rename makes the update atomic
+    pub fn write(&self, directory: &Path) -> Result<(), std::io::Error> {
+        let latest_path = directory.join(LATEST_FILE_NAME);
+        let latest_path_temp = sidecar_path(&latest_path, "temp");
+        fs::write(&latest_path_temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(latest_path_temp, latest_path)
+    }
+}
+
+/// Metadata describing a finalized snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub name: String,
+    pub snapshot_type: String,
+    pub context_type: String,
+    pub network: String,
+    pub block_hash: String,
+    // the level of the head, not recorded by the older manifests
+    pub level: Option<i32>,
+    pub created_at: String,
+    pub validated: bool,
+    pub checksum_algorithm: Option<String>,
+    pub checksum: Option<String>,
+    pub label: Option<String>,
+    pub uncompressed_size_bytes: Option<u64>,
+    // names of the parts of a split archive in order, concatenated they form the archive
+    pub parts: Option<Vec<String>>,
+    // names of the per member archives inside a members snapshot directory
+    pub members: Option<Vec<String>>,
+    // magnet link of the .torrent sidecar
+    pub magnet: Option<String>,
+}
+
+impl SnapshotManifest {
+    /// Reads the manifest belonging to the snapshot
+    pub fn read(snapshot_path: &Path) -> Result<Self, std::io::Error> {
+        let manifest = fs::read(sidecar_path(snapshot_path, MANIFEST_EXTENSION))?;
+        Ok(serde_json::from_slice(&manifest)?)
+    }
+
+    /// Writes the manifest next to the snapshot, the rename makes the update atomic
+    pub fn write(&self, snapshot_path: &Path) -> Result<(), std::io::Error> {
+        let manifest_path = sidecar_path(snapshot_path, MANIFEST_EXTENSION);
+        let manifest_path_temp = sidecar_path(&manifest_path, "temp");
+        fs::write(&manifest_path_temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(manifest_path_temp, manifest_path)
+    }
+}
+
+/// Path of a sidecar file belonging to the snapshot
+pub fn sidecar_path(snapshot_path: &Path, extension: &str) -> PathBuf {
+    let mut path = OsString::from(snapshot_path.as_os_str());
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Existing sidecar files of the snapshot
+pub fn sidecars(snapshot_path: &Path) -> Vec<PathBuf> {
+    SIDECAR_EXTENSIONS
+        .iter()
+        .map(|extension| sidecar_path(snapshot_path, extension))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Manifests of all snapshots in the target directory, laid out as `<context>/<snapshot type>/<snapshot>`
+pub fn list(snapshots_target_directory: &Path) -> Vec<SnapshotManifest> {
+    let entries = |directory: &Path| {
+        fs::read_dir(directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .collect::<Vec<PathBuf>>()
+    };
+
+    let mut manifests = vec![];
+    for context_directory in entries(snapshots_target_directory).iter().filter(|p| p.is_dir()) {
+        for type_directory in entries(context_directory).iter().filter(|p| p.is_dir()) {
+            for path in entries(type_directory) {
+                let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                if let Some(snapshot_name) = name.strip_suffix(&format!(".{}", MANIFEST_EXTENSION)) {
+                    if let Ok(manifest) = SnapshotManifest::read(&type_directory.join(snapshot_name)) {
+                        manifests.push(manifest);
+                    }
+                }
+            }
+        }
+    }
+    manifests.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    manifests
+}
+
+/// Path of the numbered part of a split archive, e.g. `<snapshot>.part000`
+pub fn part_path(snapshot_path: &Path, index: usize) -> PathBuf {
+    sidecar_path(snapshot_path, &format!("part{:03}", index))
+}
+
+/// Existing parts of a split archive in order
+pub fn parts(snapshot_path: &Path) -> Vec<PathBuf> {
+    (0..)
+        .map(|index| part_path(snapshot_path, index))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// The files making up the snapshot, the snapshot itself or the parts it was split into
+pub fn snapshot_files(snapshot_path: &Path) -> Vec<PathBuf> {
+    if snapshot_path.exists() {
+        vec![snapshot_path.to_path_buf()]
+    } else {
+        parts(snapshot_path)
+    }
+}
+
+/// The snapshot the path is a part of, if it is a part
+pub fn part_of(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let (snapshot_name, index) = name.rsplit_once(".part")?;
+    if index.len() == 3 && index.chars().all(|c| c.is_ascii_digit()) {
+        Some(path.with_file_name(snapshot_name))
+    } else {
+        None
+    }
+}
+
+/// Unique token of a snapshot run, the process id, the start time of the process in clock ticks since the boot and the
+/// start time of the snapshot, e.g. `1234-5678901-1636368116000`. A process id alone is reused, e.g. as 1 by every
+/// restart of the container
+pub fn temp_token(timestamp_millis: i64) -> String {
+    let pid = std::process::id();
+    format!("{}-{}-{}", pid, process_start_time(pid).unwrap_or_default(), timestamp_millis)
+}
+
+/// Name of the temporary file or directory written by the run, e.g. `<name>.<pid>-<ticks>-<millis>.temp`
+pub fn temp_name(name: &str, token: &str) -> String {
+    format!("{}.{}.{}", name, token, TEMP_EXTENSION)
+}
+
+/// The process id and the process start time of the run writing the temporary path, if it is one
+pub fn temp_owner(path: &Path) -> Option<(u32, u64)> {
+    let name = path.file_name()?.to_str()?;
+    let (_, token) = name.strip_suffix(&format!(".{}", TEMP_EXTENSION))?.rsplit_once('.')?;
+    let mut token = token.splitn(3, '-');
+    let (pid, start_time, timestamp_millis) = (token.next()?, token.next()?, token.next()?);
+    timestamp_millis.parse::<i64>().ok()?;
+    Some((pid.parse().ok()?, start_time.parse().ok()?))
+}
+
+/// The start time of the process in clock ticks since the boot, the 22nd field of /proc/<pid>/stat
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(Path::new("/proc").join(pid.to_string()).join("stat")).ok()?;
+    // the command name in the second field may contain spaces, it is the last parenthesized one
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Whether the path is written by a process still running, a temporary path of a dead run is a stale leftover. The
+/// process has to match by its start time too, a process id taken over by a later run does not keep the path alive
+pub fn is_live_temp(path: &Path) -> bool {
+    temp_owner(path)
+        .map(|(pid, start_time)| process_start_time(pid) == Some(start_time))
+        .unwrap_or(false)
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    SIDECAR_EXTENSIONS
+        .iter()
+        .any(|extension| name.ends_with(&format!(".{}", extension)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_temp_of_the_current_process() {
+        let token = temp_token(1636368116000);
+        assert!(is_live_temp(Path::new(&temp_name("snapshot.archive", &token))));
+
+        // the leftover of an earlier run with the same process id
+        let pid = std::process::id();
+        let stale = format!("{}-{}-{}", pid, process_start_time(pid).unwrap() + 1, 1636368116000i64);
+        assert!(!is_live_temp(Path::new(&temp_name("snapshot.archive", &stale))));
+        assert!(!is_live_temp(Path::new("snapshot.archive")));
+    }
+}
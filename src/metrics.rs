@@ -0,0 +1,140 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Upper bounds in seconds of the stage duration histogram buckets, from the lock removal to a multi hour tarball
+const DURATION_BUCKETS: &[f64] = &[1.0, 10.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0];
+
+/// Prometheus style histogram, the bucket counts are cumulative
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        self.buckets.resize(DURATION_BUCKETS.len(), 0);
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Cumulative counters of the snapshotting, updated by the controller and rendered by the API
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // label telling the instances apart on shared dashboards
+    instance_id: String,
+    bytes_written: AtomicU64,
+    bytes_rolled: AtomicU64,
+    // seconds the node has been unreachable for, 0 while it is reachable
+    node_unreachable_seconds: AtomicU64,
+    // seconds spent in each stage of the snapshotting, by stage
+    stage_durations: Mutex<BTreeMap<String, Histogram>>,
+}
+
+pub type SharedMetrics = Arc<Metrics>;
+
+impl Metrics {
+    pub fn new(instance_id: String) -> Self {
+        Self {
+            instance_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_bytes_written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_rolled(&self, bytes: u64) {
+        self.bytes_rolled.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_node_unreachable_seconds(&self, seconds: u64) {
+        self.node_unreachable_seconds.store(seconds, Ordering::Relaxed);
+    }
+
+    pub fn observe_stage_duration(&self, stage: &str, seconds: f64) {
+        if let Ok(mut stage_durations) = self.stage_durations.lock() {
+            stage_durations.entry(stage.to_string()).or_default().observe(seconds);
+        }
+    }
+
+    /// Renders the metrics in the prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let counters = [
+            (
+                "tezedge_snapshots_bytes_written_total",
+                "Total bytes of all snapshots written",
+                &self.bytes_written,
+            ),
+            (
+                "tezedge_snapshots_bytes_rolled_total",
+                "Total bytes freed by rolling old snapshots",
+                &self.bytes_rolled,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            let _ = writeln!(
+                output,
+                "{}{{instance=\"{}\"}} {}",
+                name,
+                escape_label_value(&self.instance_id),
+                value.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "tezedge_snapshots_node_unreachable_seconds";
+        let _ = writeln!(output, "# HELP {} Seconds the node has been unreachable for, 0 while it is reachable", name);
+        let _ = writeln!(output, "# TYPE {} gauge", name);
+        let _ = writeln!(
+            output,
+            "{}{{instance=\"{}\"}} {}",
+            name,
+            escape_label_value(&self.instance_id),
+            self.node_unreachable_seconds.load(Ordering::Relaxed)
+        );
+
+        let name = "tezedge_snapshots_stage_duration_seconds";
+        let _ = writeln!(output, "# HELP {} Time spent in each stage of the snapshotting", name);
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        if let Ok(stage_durations) = self.stage_durations.lock() {
+            for (stage, histogram) in stage_durations.iter() {
+                let labels = format!(
+                    "instance=\"{}\",stage=\"{}\"",
+                    escape_label_value(&self.instance_id),
+                    escape_label_value(stage)
+                );
+                for (bucket, bound) in histogram.buckets.iter().zip(DURATION_BUCKETS) {
+                    let _ = writeln!(output, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, bucket);
+                }
+                let _ = writeln!(output, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+                let _ = writeln!(output, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+                let _ = writeln!(output, "{}_count{{{}}} {}", name, labels, histogram.count);
+            }
+        }
+        output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
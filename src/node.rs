@@ -2,32 +2,218 @@
 // SPDX-License-Identifier: MIT
 
 use bollard::{
-    container::{Config, CreateContainerOptions, ListContainersOptions},
+    container::{Config, CreateContainerOptions, ListContainersOptions, RemoveContainerOptions},
     models::{HostConfig, Mount, MountTypeEnum},
-    Docker,
+    ClientVersion, Docker, API_DEFAULT_VERSION,
 };
 use chrono::Utc;
 use filetime::FileTime;
-use flate2::{write::GzEncoder, Compression};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use fs_extra::dir;
-use serde::Deserialize;
-use slog::{info, Logger, crit};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use slog::{debug, error, info, trace, warn, Logger, crit};
 use std::{
     collections::HashMap,
-    env, fs,
+    env, fmt, fs,
+    future::Future,
+    io::{BufWriter, Read, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    os::unix::fs::FileTypeExt,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     vec,
 };
 use thiserror::Error;
 use tokio::time::{Duration, Instant};
 use url::{ParseError, Url};
+use xz2::{
+    read::XzDecoder,
+    stream::{Check, MtStreamBuilder},
+    write::XzEncoder,
+};
+
+use crate::api::{SharedHealth, SharedStatus, SnapshotStatus};
+use crate::checksum;
+use crate::configuration::{
+    ChecksumAlgorithm, CompressionSetting, CompressionType, ContextType, NamingScheme, NodeLayout, SnapshotCapacity, SnapshotFormat,
+    SnapshotType,
+};
+use crate::events::EventSink;
+use crate::glob;
+use crate::manifest::{self, LatestSnapshot, SnapshotManifest};
+use crate::metrics::{Metrics, SharedMetrics};
+use crate::state::{SharedState, SnapshotState};
+use crate::torrent;
+use crate::webhook::Webhook;
+
+/// Bytes compressed per level to estimate its throughput under a compression time budget
+const COMPRESSION_SAMPLE_SIZE: u64 = 32 * 1024 * 1024;
 
-use crate::configuration::{SnapshotType, ContextType};
+/// Interval between the logs of the snapshot duration statistics
+const DURATION_STATS_LOG_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct TezosBlockHeader {
-    hash: String,
+    pub hash: String,
+    pub level: i32,
+}
+
+/// Response of the node's /version endpoint, served by both tezedge and octez
+#[derive(Clone, Debug, Deserialize)]
+pub struct NodeVersion {
+    pub version: NodeVersionNumber,
+    pub network_version: NetworkVersion,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NodeVersionNumber {
+    pub major: i64,
+    pub minor: i64,
+    pub additional_info: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NetworkVersion {
+    pub chain_name: String,
+}
+/// Settings of the snapshotting beyond the node it is attached to, the defaults are the ones of the command line
+#[derive(Clone, Debug)]
+pub struct SnapshotSettings {
+    pub full_snapshot_timeout: u64,
+    pub compression: CompressionType,
+    pub format: SnapshotFormat,
+    pub min_free_space: u64,
+    pub allow_same_head: bool,
+    pub scratch_directory: Option<PathBuf>,
+    pub post_restart_grace: u64,
+    pub compression_threads: u32,
+    pub mirror_directory: Option<PathBuf>,
+    pub mirror_capacity: Option<usize>,
+    pub warm_standby_dir: Option<PathBuf>,
+    pub name_suffix: Option<String>,
+    pub validate_snapshot: bool,
+    pub validation_rpc_port: u16,
+    pub validation_timeout: u64,
+    pub compression_buffer_size: usize,
+    pub rpc_poll_interval: u64,
+    pub name_timestamp_format: String,
+    pub naming_scheme: NamingScheme,
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+    pub max_total_size: Option<u64>,
+    pub prefer_ipv6: bool,
+    pub resolve_overrides: Vec<(String, SocketAddr)>,
+    pub container_name_suffix: String,
+    pub monitoring_container_name_suffix: Option<String>,
+    pub label: Option<String>,
+    pub label_in_name: bool,
+    pub fsync: bool,
+    pub chown: Option<(u32, u32)>,
+    pub node_layout: NodeLayout,
+    pub watchdog_timeout: Option<u64>,
+    pub unreachable_grace: u64,
+    pub no_roll: bool,
+    pub rpc_ca_cert: Option<PathBuf>,
+    pub rpc_insecure: bool,
+    pub skip_lock_removal: bool,
+    pub strict_lock: bool,
+    pub split_size: Option<u64>,
+    pub recovery_timeout: u64,
+    pub output_pipe: Option<PathBuf>,
+    pub restart_cooldown: u64,
+    pub restart_failure_threshold: u32,
+    pub clean_bootstrap_before_full: bool,
+    pub lock_release_timeout: u64,
+    pub additional_target_directories: Vec<PathBuf>,
+    pub docker_timeout: u64,
+    pub docker_retries: u32,
+    pub checkpoint_rpc_path: Option<String>,
+    pub archive_compression: Option<CompressionSetting>,
+    pub full_compression: Option<CompressionSetting>,
+    pub stop_confirm_timeout: u64,
+    pub post_stop_delay: u64,
+    pub docker_api_version: Option<(usize, usize)>,
+    pub host_data_path: Option<PathBuf>,
+    pub host_snapshots_path: Option<PathBuf>,
+    pub compression_time_budget: Option<u64>,
+    pub compression_nice: Option<i32>,
+    pub external_compressor: Option<String>,
+    pub external_compressor_extension: String,
+    pub context_include: Vec<String>,
+    pub torrent_trackers: Vec<Url>,
+    pub torrent_web_seeds: Vec<Url>,
+    pub min_interval: u64,
+}
+
+impl Default for SnapshotSettings {
+    fn default() -> Self {
+        Self {
+            full_snapshot_timeout: 43200,
+            compression: CompressionType::Gzip,
+            format: SnapshotFormat::Tar,
+            min_free_space: 0,
+            allow_same_head: false,
+            scratch_directory: None,
+            post_restart_grace: 0,
+            compression_threads: 1,
+            mirror_directory: None,
+            mirror_capacity: None,
+            warm_standby_dir: None,
+            name_suffix: None,
+            validate_snapshot: false,
+            validation_rpc_port: 18799,
+            validation_timeout: 1800,
+            compression_buffer_size: 1024 * 1024,
+            rpc_poll_interval: 0,
+            name_timestamp_format: String::from("%Y%m%d-%H%M%S"),
+            naming_scheme: NamingScheme::Tezedge,
+            checksum_algorithm: None,
+            max_total_size: None,
+            prefer_ipv6: false,
+            resolve_overrides: vec![],
+            container_name_suffix: String::from("-{context}-{network}"),
+            monitoring_container_name_suffix: None,
+            label: None,
+            label_in_name: false,
+            fsync: true,
+            chown: None,
+            node_layout: NodeLayout::V1,
+            watchdog_timeout: None,
+            unreachable_grace: 3600,
+            no_roll: false,
+            rpc_ca_cert: None,
+            rpc_insecure: false,
+            skip_lock_removal: false,
+            strict_lock: false,
+            split_size: None,
+            recovery_timeout: 600,
+            output_pipe: None,
+            restart_cooldown: 30,
+            restart_failure_threshold: 3,
+            clean_bootstrap_before_full: false,
+            lock_release_timeout: 30,
+            additional_target_directories: vec![],
+            docker_timeout: 120,
+            docker_retries: 2,
+            checkpoint_rpc_path: None,
+            archive_compression: None,
+            full_compression: None,
+            stop_confirm_timeout: 60,
+            post_stop_delay: 0,
+            docker_api_version: None,
+            host_data_path: None,
+            host_snapshots_path: None,
+            compression_time_budget: None,
+            compression_nice: None,
+            external_compressor: None,
+            external_compressor_extension: String::from("tar"),
+            context_include: vec![],
+            torrent_trackers: vec![],
+            torrent_web_seeds: vec![],
+            min_interval: 0,
+        }
+    }
 }
+
 pub struct TezedgeNodeController {
     url: Url,
     node_container_name: String,
@@ -36,11 +222,154 @@ pub struct TezedgeNodeController {
     database_directory: PathBuf,
     last_snapshot_timestamp: Option<Instant>,
     snapshots_target_directory: PathBuf,
+    additional_target_directories: Vec<PathBuf>,
+    // the target directory of the snapshot in progress
+    placement_directory: PathBuf,
     full_snapshot_image: String,
+    // time in seconds the full snapshotting container has to finish the export
+    full_snapshot_timeout: u64,
     context_type: ContextType,
+    compression: CompressionType,
+    format: SnapshotFormat,
+    min_free_space: u64,
+    allow_same_head: bool,
+    last_snapshot_hash: Option<String>,
+    scratch_directory: Option<PathBuf>,
+    post_restart_grace: u64,
+    last_restart_timestamp: Option<Instant>,
+    stage: SnapshotStage,
+    stage_started: Instant,
+    // time spent in each stage of the current snapshot
+    stage_durations: HashMap<SnapshotStage, Duration>,
+    // bytes per second of the compression levels, sampled before the node is stopped
+    compression_throughput: Vec<(CompressionSetting, f64)>,
+    // recent snapshot durations, persisted in the target directory
+    state: SharedState,
+    last_duration_stats_log: Option<Instant>,
+    // makes the temporary names of the snapshot in progress unique, see manifest::temp_token
+    temp_token: String,
+    compression_threads: u32,
+    mirror_directory: Option<PathBuf>,
+    mirror_capacity: Option<usize>,
+    warm_standby_dir: Option<PathBuf>,
+    name_suffix: Option<String>,
+    validate_snapshot: bool,
+    validation_rpc_port: u16,
+    validation_timeout: u64,
+    status: SharedStatus,
+    health: SharedHealth,
+    metrics: SharedMetrics,
+    compression_buffer_size: usize,
+    rpc_poll_interval: u64,
+    last_rpc_poll: Option<(Instant, bool)>,
+    name_timestamp_format: String,
+    naming_scheme: NamingScheme,
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    max_total_size: Option<u64>,
+    http_client: reqwest::Client,
+    label: Option<String>,
+    label_in_name: bool,
+    fsync: bool,
+    chown: Option<(u32, u32)>,
+    node_layout: NodeLayout,
+    watchdog_timeout: Option<u64>,
+    unreachable_since: Option<Instant>,
+    unreachable_grace: u64,
+    // start of the current outage, unlike unreachable_since not reset by the watchdog restarts
+    outage_since: Option<Instant>,
+    // warnings logged about the current outage, each one after twice the time of the previous
+    unreachable_warnings: u32,
+    no_roll: bool,
+    skip_lock_removal: bool,
+    strict_lock: bool,
+    split_size: Option<u64>,
+    recovery_timeout: u64,
+    output_pipe: Option<PathBuf>,
+    restart_cooldown: u64,
+    restart_failure_threshold: u32,
+    restart_failures: u32,
+    clean_bootstrap_before_full: bool,
+    lock_release_timeout: u64,
+    docker_timeout: u64,
+    docker_retries: u32,
+    checkpoint_rpc_path: Option<String>,
+    node_checkpointed: bool,
+    archive_compression: Option<CompressionSetting>,
+    full_compression: Option<CompressionSetting>,
+    stop_confirm_timeout: u64,
+    post_stop_delay: u64,
+    docker_api_version: Option<(usize, usize)>,
+    // bind sources on the docker host of the database and the primary target directory
+    host_data_path: Option<String>,
+    host_snapshots_path: Option<String>,
+    compression_time_budget: Option<u64>,
+    compression_nice: Option<i32>,
+    external_compressor: Option<String>,
+    external_compressor_extension: String,
+    context_include: Vec<String>,
+    torrent_trackers: Vec<Url>,
+    torrent_web_seeds: Vec<Url>,
+    min_interval: u64,
+    webhook: Webhook,
+    events: EventSink,
     log: Logger,
 }
 
+/// The step of the snapshotting process the controller is currently in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotStage {
+    Idle,
+    CheckingHead,
+    StoppingNode,
+    Rolling,
+    RemovingLock,
+    RunningSnapshotContainer,
+    CreatingArchive,
+    Finalizing,
+    StartingNode,
+    WaitingForRecovery,
+    ValidatingSnapshot,
+    PostProcessing,
+}
+
+impl fmt::Display for SnapshotStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotStage::Idle => write!(f, "idle"),
+            SnapshotStage::CheckingHead => write!(f, "checking_head"),
+            SnapshotStage::StoppingNode => write!(f, "stopping_node"),
+            SnapshotStage::Rolling => write!(f, "rolling"),
+            SnapshotStage::RemovingLock => write!(f, "removing_lock"),
+            SnapshotStage::RunningSnapshotContainer => write!(f, "running_snapshot_container"),
+            SnapshotStage::CreatingArchive => write!(f, "creating_archive"),
+            SnapshotStage::Finalizing => write!(f, "finalizing"),
+            SnapshotStage::StartingNode => write!(f, "starting_node"),
+            SnapshotStage::WaitingForRecovery => write!(f, "waiting_for_recovery"),
+            SnapshotStage::ValidatingSnapshot => write!(f, "validating_snapshot"),
+            SnapshotStage::PostProcessing => write!(f, "post_processing"),
+        }
+    }
+}
+
+/// Diagnostics written to the target directory when the snapshotting task dies on a fatal error
+#[derive(Debug, Serialize)]
+struct LastError {
+    error_chain: Vec<String>,
+    stage: SnapshotStage,
+    timestamp: String,
+}
+
+/// Sub-steps of a snapshot completed for a head, lets a restarted process resume the remaining ones
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotProgress {
+    block_hash: String,
+    snapshot_name: String,
+    created_at: String,
+    // snapshot type, path and uncompressed size in bytes
+    completed: Vec<(String, PathBuf, u64)>,
+}
+
 #[derive(Debug, Error)]
 pub enum TezedgeNodeControllerError {
     #[error("The defined tezedge node is unreachable")]
@@ -49,12 +378,36 @@ pub enum TezedgeNodeControllerError {
     MalformedUrl(#[from] ParseError),
     #[error("Request to the node failed: {0}")]
     FailedRequest(#[from] reqwest::Error),
+    #[error("The node responded with an unexpected body: {0}")]
+    MalformedResponse(#[from] serde_json::Error),
+    #[error("Container {0} does not exist, check the container names and --container-name-suffix")]
+    ContainerNotFound(String),
+    #[error("The database directory does not match the {0} node layout, {1} is missing")]
+    NodeLayoutMismatch(String, String),
     #[error("Docker operation failed: {0}")]
     DockerError(#[from] bollard::errors::Error),
+    #[error("The context lock {0} was not released by the stopped node, it may still be writing")]
+    LockNotReleased(String),
+    #[error("Container {0} was still running {1}s after it was stopped")]
+    NodeStillRunning(String, u64),
+    #[error("Docker operation {0} did not complete within {1}s")]
+    DockerTimeout(String, u64),
     #[error("Filesystem operation failed: {0}")]
     FilesystemError(#[from] fs_extra::error::Error),
     #[error("Io error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("Not enough free space on the snapshot target: {available} bytes available, {required} bytes required")]
+    InsufficientFreeSpace { available: u64, required: u64 },
+    #[error("{} snapshot step(s) failed: {}", .0.len(), .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    SnapshotsFailed(Vec<TezedgeNodeControllerError>),
+    #[error("The snapshot target {0} is not writable: {1}")]
+    TargetNotWritable(String, std::io::Error),
+    #[error("Snapshot validation failed: {0}")]
+    ValidationFailed(String),
+    #[error("The output pipe {0} is not a named pipe")]
+    NotAPipe(String),
+    #[error("The full snapshotting container {0} did not finish within {1}s")]
+    FullSnapshotTimeout(String, u64),
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -68,65 +421,505 @@ impl TezedgeNodeController {
         snapshots_target_directory: PathBuf,
         full_snapshot_image: String,
         context_type: ContextType,
+        settings: SnapshotSettings,
+        webhook: Webhook,
+        events: EventSink,
+        instance_id: String,
         log: Logger,
     ) -> Self {
-        let node_container_name = format!("{}-{}-{}", node_container_name, context_type.to_string(), network);
-        let monitoring_container_name = format!("{}-{}-{}", monitoring_container_name, context_type.to_string(), network);
+        let SnapshotSettings {
+            full_snapshot_timeout,
+            compression,
+            format,
+            min_free_space,
+            allow_same_head,
+            scratch_directory,
+            post_restart_grace,
+            compression_threads,
+            mirror_directory,
+            mirror_capacity,
+            warm_standby_dir,
+            name_suffix,
+            validate_snapshot,
+            validation_rpc_port,
+            validation_timeout,
+            compression_buffer_size,
+            rpc_poll_interval,
+            name_timestamp_format,
+            naming_scheme,
+            checksum_algorithm,
+            max_total_size,
+            prefer_ipv6,
+            resolve_overrides,
+            container_name_suffix,
+            monitoring_container_name_suffix,
+            label,
+            label_in_name,
+            fsync,
+            chown,
+            node_layout,
+            watchdog_timeout,
+            unreachable_grace,
+            no_roll,
+            rpc_ca_cert,
+            rpc_insecure,
+            skip_lock_removal,
+            strict_lock,
+            split_size,
+            recovery_timeout,
+            output_pipe,
+            restart_cooldown,
+            restart_failure_threshold,
+            clean_bootstrap_before_full,
+            lock_release_timeout,
+            additional_target_directories,
+            docker_timeout,
+            docker_retries,
+            checkpoint_rpc_path,
+            archive_compression,
+            full_compression,
+            stop_confirm_timeout,
+            post_stop_delay,
+            docker_api_version,
+            host_data_path,
+            host_snapshots_path,
+            compression_time_budget,
+            compression_nice,
+            external_compressor,
+            external_compressor_extension,
+            context_include,
+            torrent_trackers,
+            torrent_web_seeds,
+            min_interval,
+        } = settings;
+
+        // binding to the unspecified IPv6 address only allows IPv6 connections to the node
+        let mut http_client = reqwest::Client::builder();
+        if prefer_ipv6 {
+            http_client = http_client.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        }
+        for (host, address) in resolve_overrides {
+            http_client = http_client.resolve(&host, address);
+        }
+        if let Some(rpc_ca_cert) = rpc_ca_cert {
+            let pem = fs::read(&rpc_ca_cert).expect("Failed to read the rpc CA certificate");
+            let certificate = reqwest::Certificate::from_pem(&pem).expect("Expected a PEM encoded CA certificate");
+            http_client = http_client.add_root_certificate(certificate);
+        }
+        if rpc_insecure {
+            warn!(log, "TLS certificate validation of the node rpc is disabled");
+            http_client = http_client.danger_accept_invalid_certs(true);
+        }
+        let http_client = http_client.build().expect("Failed to build the HTTP client");
+
+        // the flags take precedence over the variables set by the docker-compose files
+        let host_data_path = host_data_path
+            .map(|path| path.to_string_lossy().to_string())
+            .or_else(|| env::var("TEZEDGE_VOLUME_PATH").ok());
+        let host_snapshots_path = host_snapshots_path
+            .map(|path| path.to_string_lossy().to_string())
+            .or_else(|| env::var("TEZEDGE_SNAPSHOTS_VOLUME_PATH").ok());
+        // outside of a container the paths seen here are the host paths, inside they are most likely not
+        if is_containerized() && (host_data_path.is_none() || host_snapshots_path.is_none()) {
+            warn!(
+                log,
+                "Running in a container without --host-data-path or --host-snapshots-path, \
+                 the full snapshot container bind mounts the container paths as host paths"
+            );
+        }
+
+        let substitute = |suffix: &str| {
+            suffix
+                .replace("{context}", &context_type.to_string())
+                .replace("{network}", &network)
+        };
+        let monitoring_container_name_suffix = substitute(
+            monitoring_container_name_suffix
+                .as_deref()
+                .unwrap_or(&container_name_suffix),
+        );
+        let node_container_name = format!("{}{}", node_container_name, substitute(&container_name_suffix));
+        let monitoring_container_name = format!("{}{}", monitoring_container_name, monitoring_container_name_suffix);
+
+        let state = SnapshotState::read(&snapshots_target_directory).unwrap_or_else(|e| {
+            warn!(log, "Failed to read the snapshot state, the duration history starts empty: {}", e);
+            SnapshotState::default()
+        });
         Self {
             url,
             node_container_name,
             monitoring_container_name,
             network,
             database_directory,
+            placement_directory: snapshots_target_directory.clone(),
             snapshots_target_directory,
+            additional_target_directories,
             last_snapshot_timestamp: None,
             full_snapshot_image,
+            full_snapshot_timeout,
             context_type,
+            compression,
+            format,
+            min_free_space,
+            allow_same_head,
+            last_snapshot_hash: None,
+            scratch_directory,
+            post_restart_grace,
+            last_restart_timestamp: None,
+            stage: SnapshotStage::Idle,
+            stage_started: Instant::now(),
+            stage_durations: HashMap::new(),
+            compression_throughput: Vec::new(),
+            state: Arc::new(std::sync::RwLock::new(state)),
+            last_duration_stats_log: None,
+            temp_token: manifest::temp_token(Utc::now().timestamp_millis()),
+            compression_threads,
+            mirror_directory,
+            mirror_capacity,
+            warm_standby_dir,
+            name_suffix,
+            validate_snapshot,
+            validation_rpc_port,
+            validation_timeout,
+            status: SharedStatus::default(),
+            health: SharedHealth::default(),
+            metrics: Arc::new(Metrics::new(instance_id)),
+            compression_buffer_size,
+            rpc_poll_interval,
+            last_rpc_poll: None,
+            name_timestamp_format,
+            naming_scheme,
+            checksum_algorithm,
+            max_total_size,
+            http_client,
+            label,
+            label_in_name,
+            fsync,
+            chown,
+            node_layout,
+            watchdog_timeout,
+            unreachable_since: None,
+            unreachable_grace,
+            outage_since: None,
+            unreachable_warnings: 0,
+            no_roll,
+            skip_lock_removal,
+            strict_lock,
+            split_size,
+            recovery_timeout,
+            output_pipe,
+            restart_cooldown,
+            restart_failure_threshold,
+            restart_failures: 0,
+            clean_bootstrap_before_full,
+            lock_release_timeout,
+            docker_timeout,
+            docker_retries,
+            checkpoint_rpc_path,
+            node_checkpointed: false,
+            archive_compression,
+            full_compression,
+            stop_confirm_timeout,
+            post_stop_delay,
+            docker_api_version,
+            host_data_path,
+            host_snapshots_path,
+            compression_time_budget,
+            compression_nice,
+            external_compressor,
+            external_compressor_extension,
+            context_include,
+            torrent_trackers,
+            torrent_web_seeds,
+            min_interval,
+            webhook,
+            events,
             log,
         }
     }
 
+    /// The progress of the snapshotting, shared with the API
+    pub fn status(&self) -> SharedStatus {
+        self.status.clone()
+    }
+
+    /// The inputs of the health probes, shared with the API
+    pub fn health(&self) -> SharedHealth {
+        self.health.clone()
+    }
+
+    /// The recent snapshot durations, shared with the API
+    pub fn state(&self) -> SharedState {
+        self.state.clone()
+    }
+
+    /// The cumulative counters, shared with the API
+    pub fn metrics(&self) -> SharedMetrics {
+        self.metrics.clone()
+    }
+
+    fn set_stage(&mut self, stage: SnapshotStage) {
+        // the time between snapshots is not part of the snapshotting
+        if self.stage != SnapshotStage::Idle {
+            let elapsed = self.stage_started.elapsed();
+            *self.stage_durations.entry(self.stage).or_default() += elapsed;
+            self.metrics.observe_stage_duration(&self.stage.to_string(), elapsed.as_secs_f64());
+        }
+        self.stage = stage;
+        self.stage_started = Instant::now();
+        if let Ok(mut status) = self.status.write() {
+            status.phase = stage;
+            if stage == SnapshotStage::Idle {
+                *status = SnapshotStatus::default();
+            }
+        }
+    }
+
+    /// Bind source of the target directory of the snapshot in progress, the host path override only maps the
+    /// primary target directory
+    fn snapshot_host_path(&self) -> String {
+        match &self.host_snapshots_path {
+            Some(host_path) if self.placement_directory == self.snapshots_target_directory => host_path.clone(),
+            _ => self.placement_directory.to_string_lossy().to_string(),
+        }
+    }
+
+    /// Connects to the docker daemon, with the pinned API version when configured
+    fn docker(&self) -> Result<Docker, TezedgeNodeControllerError> {
+        let client_version = match self.docker_api_version {
+            Some((major_version, minor_version)) => ClientVersion {
+                major_version,
+                minor_version,
+            },
+            None => *API_DEFAULT_VERSION,
+        };
+        Ok(Docker::connect_with_socket("unix:///var/run/docker.sock", 120, &client_version)?)
+    }
+
     /// Gets the head header from the node
     pub async fn get_head(&self) -> Result<TezosBlockHeader, TezedgeNodeControllerError> {
         let header_url = self.url.join("chains/main/blocks/head/header")?;
-        let head_header = reqwest::get(header_url).await?.json().await?;
+        let head_header = self.rpc_get(header_url).await?;
 
         Ok(head_header)
     }
 
+    /// Gets the version of the node, only a tezos RPC server responds with a matching body
+    pub async fn get_version(&self) -> Result<NodeVersion, TezedgeNodeControllerError> {
+        let version_url = self.url.join("version")?;
+        let version = self.rpc_get(version_url).await?;
+
+        Ok(version)
+    }
+
+    /// Gets the json body of the rpc, the request and the response are traced for reconstructing a failed snapshot
+    async fn rpc_get<T: DeserializeOwned>(&self, url: Url) -> Result<T, TezedgeNodeControllerError> {
+        trace!(self.log, "RPC request"; "method" => "GET", "url" => redacted_url(&url));
+        let response = self.http_client.get(url).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        trace!(self.log, "RPC response"; "status" => status.as_u16(), "body" => &body);
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Version of the docker daemon
+    pub async fn docker_version(&self) -> Result<String, TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+        let version = docker.version().await?;
+        Ok(format!(
+            "{} (client api {}, daemon supports api {} to {})",
+            version.version.unwrap_or_default(),
+            docker.client_version(),
+            version.min_api_version.unwrap_or_default(),
+            version.api_version.unwrap_or_default()
+        ))
+    }
+
+    /// Digest of the full snapshot image, the image id when it was not pulled from a registry
+    pub async fn full_snapshot_image_digest(&self) -> Result<String, TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+        let image = docker.inspect_image(&self.full_snapshot_image).await?;
+        Ok(image
+            .repo_digests
+            .and_then(|digests| digests.into_iter().next())
+            .unwrap_or(image.id))
+    }
+
     /// Stops the tezedge container
     pub async fn stop(&self) -> Result<(), TezedgeNodeControllerError> {
-        let docker = Docker::connect_with_socket_defaults()?;
+        let docker = self.docker()?;
 
-        docker
-            .stop_container(&self.node_container_name, None)
-            .await?;
+        // an already stopped container is not an error, a retry after a timeout may find it stopped
+        trace!(self.log, "Docker stop_container"; "container" => &self.node_container_name);
+        match self
+            .with_docker_retry("stop node", || docker.stop_container(&self.node_container_name, None))
+            .await
+        {
+            Ok(()) | Err(TezedgeNodeControllerError::DockerError(bollard::errors::Error::DockerResponseNotModifiedError { .. })) => (),
+            Err(e) => return Err(e),
+        }
+
+        // a slow stopping node could still be writing to the database directory
+        let started = Instant::now();
+        while self.is_running(&self.node_container_name).await? {
+            if started.elapsed() >= Duration::from_secs(self.stop_confirm_timeout) {
+                return Err(TezedgeNodeControllerError::NodeStillRunning(
+                    self.node_container_name.clone(),
+                    self.stop_confirm_timeout,
+                ));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
 
         info!(self.log, "Tezedge node container stopped");
 
-        docker
-            .stop_container(&self.monitoring_container_name, None)
-            .await?;
+        trace!(self.log, "Docker stop_container"; "container" => &self.monitoring_container_name);
+        match self
+            .with_docker_retry("stop monitoring", || docker.stop_container(&self.monitoring_container_name, None))
+            .await
+        {
+            Ok(()) | Err(TezedgeNodeControllerError::DockerError(bollard::errors::Error::DockerResponseNotModifiedError { .. })) => (),
+            Err(e) => return Err(e),
+        }
 
         Ok(())
     }
 
     /// Starts the tezedge container
-    pub async fn start(&self) -> Result<(), TezedgeNodeControllerError> {
-        let docker = Docker::connect_with_socket_defaults()?;
+    pub async fn start(&mut self) -> Result<(), TezedgeNodeControllerError> {
+        let docker = self.docker()?;
 
-        docker
-            .start_container::<String>(&self.node_container_name, None)
-            .await?;
+        // an already running container is not an error, a retry after a partial start only starts the rest
+        trace!(self.log, "Docker start_container"; "container" => &self.node_container_name);
+        match self
+            .with_docker_retry("start node", || docker.start_container::<String>(&self.node_container_name, None))
+            .await
+        {
+            Ok(()) | Err(TezedgeNodeControllerError::DockerError(bollard::errors::Error::DockerResponseNotModifiedError { .. })) => (),
+            Err(e) => return Err(e),
+        }
 
         info!(self.log, "Tezedge node container started");
+        self.last_restart_timestamp = Some(Instant::now());
 
-        docker
-            .start_container::<String>(&self.monitoring_container_name, None)
-            .await?;
+        trace!(self.log, "Docker start_container"; "container" => &self.monitoring_container_name);
+        match self
+            .with_docker_retry("start monitoring", || docker.start_container::<String>(&self.monitoring_container_name, None))
+            .await
+        {
+            Ok(()) | Err(TezedgeNodeControllerError::DockerError(bollard::errors::Error::DockerResponseNotModifiedError { .. })) => (),
+            Err(e) => return Err(e),
+        }
 
         info!(self.log, "Tezedge node monitoring container started");
-        // TODO: preform a health check with get_head
+
+        Ok(())
+    }
+
+    /// Asks the node to flush its context to a consistent on-disk state
+    async fn checkpoint(&self, checkpoint_rpc_path: &str) -> Result<(), TezedgeNodeControllerError> {
+        let checkpoint_url = self.url.join(checkpoint_rpc_path)?;
+        trace!(self.log, "RPC request"; "method" => "POST", "url" => redacted_url(&checkpoint_url));
+        let response = self.http_client.post(checkpoint_url).send().await?;
+        trace!(self.log, "RPC response"; "status" => response.status().as_u16());
+        response.error_for_status()?;
+        Ok(())
+    }
+
+    /// Runs a docker operation under the docker timeout, retrying the failures of a momentarily busy daemon
+    async fn with_docker_retry<T, F, Fut>(&self, operation: &str, f: F) -> Result<T, TezedgeNodeControllerError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, bollard::errors::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            trace!(self.log, "Docker request"; "operation" => operation, "attempt" => attempt + 1);
+            let error = match tokio::time::timeout(Duration::from_secs(self.docker_timeout), f()).await {
+                Ok(Ok(result)) => {
+                    trace!(self.log, "Docker response"; "operation" => operation, "result" => "ok");
+                    return Ok(result);
+                }
+                Ok(Err(e)) if !is_retryable_docker_error(&e) => {
+                    trace!(self.log, "Docker response"; "operation" => operation, "result" => e.to_string());
+                    return Err(e.into());
+                }
+                Ok(Err(e)) => TezedgeNodeControllerError::DockerError(e),
+                Err(_) => TezedgeNodeControllerError::DockerTimeout(operation.to_string(), self.docker_timeout),
+            };
+            attempt += 1;
+            if attempt > self.docker_retries {
+                return Err(error);
+            }
+            warn!(
+                self.log,
+                "Docker operation {} failed, retrying ({}/{}): {}",
+                operation,
+                attempt,
+                self.docker_retries,
+                error
+            );
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    /// Starts the containers, retrying after a doubling cooldown so a broken docker or node is not hammered
+    async fn start_with_cooldown(&mut self) -> Result<(), TezedgeNodeControllerError> {
+        let mut attempts = 0;
+        loop {
+            let e = match self.start().await {
+                Ok(()) => {
+                    self.restart_failures = 0;
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            attempts += 1;
+            self.restart_failures += 1;
+
+            // the failures are counted across snapshots, escalate once per outage
+            if self.restart_failures == self.restart_failure_threshold {
+                let message = format!("{} consecutive node restarts failed, last error: {}", self.restart_failures, e);
+                crit!(self.log, "{}", message);
+                self.webhook.notify("restart_failed", &message).await;
+            }
+            if attempts >= self.restart_failure_threshold {
+                return Err(e);
+            }
+
+            let cooldown = self.restart_cooldown.saturating_mul(1 << (self.restart_failures - 1).min(6));
+            warn!(self.log, "Failed to start the node, retrying in {}s: {}", cooldown, e);
+            tokio::time::sleep(Duration::from_secs(cooldown)).await;
+        }
+    }
+
+    /// Checks the node and monitoring containers are running, starting the stopped ones when recover is set
+    pub async fn check_node_running(&mut self, recover: bool) -> Result<(), TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+
+        for container_name in [self.node_container_name.clone(), self.monitoring_container_name.clone()] {
+            // a misnamed container would otherwise only surface once the first snapshot stops it
+            match docker.inspect_container(&container_name, None).await {
+                Err(bollard::errors::Error::DockerResponseNotFoundError { .. }) => {
+                    return Err(TezedgeNodeControllerError::ContainerNotFound(container_name))
+                }
+                Err(e) => return Err(e.into()),
+                Ok(_) => (),
+            }
+
+            if self.is_running(&container_name).await? {
+                continue;
+            }
+
+            if recover {
+                warn!(self.log, "Container {} is not running, starting it", container_name);
+                docker.start_container::<String>(&container_name, None).await?;
+                self.last_restart_timestamp = Some(Instant::now());
+            } else {
+                warn!(self.log, "Container {} is not running, no snapshot can be taken until it is started", container_name);
+            }
+        }
 
         Ok(())
     }
@@ -135,68 +928,118 @@ impl TezedgeNodeController {
         &mut self,
         snapshot_capacity: usize,
         snapshot_name: &str,
-    ) -> Result<(), TezedgeNodeControllerError> {
+    ) -> Result<(PathBuf, u64), TezedgeNodeControllerError> {
         // we start by giving the directory a "temporary" name so we can ignore it until the copy has finished
-        let snapshot_name_temp = format!("{}.temp", snapshot_name);
+        let snapshot_name_temp = manifest::temp_name(snapshot_name, &self.temp_token);
 
-        let archive_snapshot_name = format!("{}.archive", snapshot_name);
+        let compression = self.compression_of("archive");
+        let archive_snapshot_name = self.final_snapshot_name(format!("{}.archive", snapshot_name), &compression);
 
-        let archive_snapshots_target_directory = self.snapshots_target_directory.join(self.context_type.to_string()).join("archive");
+        let archive_snapshots_target_directory = self.placement_directory.join(self.context_type.to_string()).join("archive");
 
         if !archive_snapshots_target_directory.exists() {
             dir::create_all(&archive_snapshots_target_directory, false)?;
         }
 
+        self.set_stage(SnapshotStage::Rolling);
         info!(self.log, "[Archive] Checking for rolling older snapshots (1/4)");
 
         // identify and remove the oldest snapshot in the target dir, if we are over capacity
         self.check_rolling(&archive_snapshots_target_directory, snapshot_capacity)?;
 
         // 2. copy out the database directories to a temp folder
+        self.set_stage(SnapshotStage::RemovingLock);
         info!(self.log, "[Archive] Removing lock file (2/4)");
+        self.remove_lock_file().await?;
 
-        let to_remove = vec![self.database_directory.join("context/index/lock")];
-        fs_extra::remove_items(&to_remove)?;
-
-        info!(self.log, "[Archive] Creating tarball (3/4)");
-        self.create_tezedge_tar_archive(&snapshot_name_temp, &self.database_directory, &archive_snapshots_target_directory)?;
+        self.set_stage(SnapshotStage::CreatingArchive);
+        let uncompressed_size = match self.format {
+            SnapshotFormat::Tar => {
+                info!(self.log, "[Archive] Creating tarball (3/4)");
+                self.create_tezedge_tar_archive(
+                    &snapshot_name_temp,
+                    &self.database_directory,
+                    &archive_snapshots_target_directory,
+                    self.node_layout.members(),
+                    &compression,
+                )?
+            }
+            SnapshotFormat::Dir => {
+                info!(self.log, "[Archive] Copying the database directories (3/4)");
+                self.create_tezedge_dir_copy(&snapshot_name_temp, &self.database_directory, &archive_snapshots_target_directory)?;
+                snapshot_size(&archive_snapshots_target_directory.join(&snapshot_name_temp))?
+            }
+            SnapshotFormat::Members => {
+                info!(self.log, "[Archive] Creating a tarball per database directory (3/4)");
+                self.create_tezedge_member_archives(
+                    &snapshot_name_temp,
+                    &self.database_directory,
+                    &archive_snapshots_target_directory,
+                    &compression,
+                )?
+            }
+        };
 
         // . move to the destination
+        self.set_stage(SnapshotStage::Finalizing);
         info!(self.log, "[Archive] Removing .temp from the snapshot directory (4/4)");
         // rename to the final name removing .temp indicating that the copy has been complete
-        fs::rename(
-            archive_snapshots_target_directory.join(&snapshot_name_temp),
-            archive_snapshots_target_directory.join(&archive_snapshot_name),
-        )?;
+        self.publish_snapshot(&archive_snapshots_target_directory, &snapshot_name_temp, &archive_snapshot_name)?;
 
-        Ok(())
+        Ok((archive_snapshots_target_directory.join(&archive_snapshot_name), uncompressed_size))
+    }
+
+    /// Streams the archive into the output pipe, the consumer on the other end handles its persistence
+    async fn stream_archive_snapshot(&mut self, output_pipe: &Path) -> Result<u64, TezedgeNodeControllerError> {
+        if !fs::metadata(output_pipe)?.file_type().is_fifo() {
+            return Err(TezedgeNodeControllerError::NotAPipe(output_pipe.to_string_lossy().to_string()));
+        }
+
+        self.set_stage(SnapshotStage::RemovingLock);
+        info!(self.log, "[Archive] Removing lock file (1/2)");
+        self.remove_lock_file().await?;
+
+        self.set_stage(SnapshotStage::CreatingArchive);
+        info!(self.log, "[Archive] Waiting for a reader on {}", output_pipe.to_string_lossy());
+        // blocks until the consumer opens the pipe for reading, the node stays stopped meanwhile
+        let pipe = fs::OpenOptions::new().write(true).open(output_pipe)?;
+
+        info!(self.log, "[Archive] Streaming tarball into {} (2/2)", output_pipe.to_string_lossy());
+        let (pipe, uncompressed_size) = self.compress_tezedge_dirs(
+            BufWriter::with_capacity(self.compression_buffer_size, pipe),
+            &self.database_directory,
+            self.node_layout.members(),
+            &self.compression_of("archive"),
+        )?;
+        pipe.into_inner().map_err(|e| e.into_error())?;
+        Ok(uncompressed_size)
     }
 
     async fn take_full_snapshot(
-        &self,
+        &mut self,
         snapshot_name: &str,
         snapshot_capacity: usize,
-    ) -> Result<(), TezedgeNodeControllerError> {
-        let docker = Docker::connect_with_socket_defaults()?;
+    ) -> Result<(PathBuf, u64), TezedgeNodeControllerError> {
+        let docker = self.docker()?;
 
-        if self.database_directory.join("context/index/lock").exists() {
-            let to_remove = vec![self.database_directory.join("context/index/lock")];
-            fs_extra::remove_items(&to_remove)?;
-        }
+        self.remove_lock_file().await?;
 
         // let image = "tezedge/tezedge:no-snapshot-timeout";
         let cont_name = format!("tezedge-snapshots-full-{}-{}", &self.context_type.to_string(), self.network);
         let snapshot_name = format!("{}.full", snapshot_name);
-        let snapshot_name_dir_temp = format!("{}-dir.temp", &snapshot_name);
-        let snapshot_name_temp = format!("{}.temp", &snapshot_name);
+        let compression = self.compression_of("full");
+        let full_snapshot_name = self.final_snapshot_name(snapshot_name.clone(), &compression);
+        let snapshot_name_dir_temp = manifest::temp_name(&format!("{}-dir", &snapshot_name), &self.temp_token);
+        let snapshot_name_temp = manifest::temp_name(&snapshot_name, &self.temp_token);
 
-        let full_snapshots_target_directory = self.snapshots_target_directory.join(self.context_type.to_string()).join("full");
+        let full_snapshots_target_directory = self.placement_directory.join(self.context_type.to_string()).join("full");
 
         if !full_snapshots_target_directory.exists() {
             dir::create_all(&full_snapshots_target_directory, false)?;
         }
 
         // check for rolling
+        self.set_stage(SnapshotStage::Rolling);
         info!(self.log, "[Full] Checking for rolling older snapshots (1/7)");
         self.check_rolling(&full_snapshots_target_directory, snapshot_capacity)?;
 
@@ -204,6 +1047,7 @@ impl TezedgeNodeController {
         if !snapshot_path.exists() {
             dir::create_all(&snapshot_path, false)?;
         }
+        self.set_stage(SnapshotStage::RunningSnapshotContainer);
         let snapshot_path_string = snapshot_path.to_string_lossy().to_string();
         let database_path_string = self.database_directory.to_string_lossy().to_string();
         let with_context_type = self.context_type.to_string();
@@ -228,20 +1072,15 @@ impl TezedgeNodeController {
         ];
 
         info!(self.log, "[Full] Creating full snapshotting tezedge container (2/7)");
-        let snapshot_host_path = env::var("TEZEDGE_SNAPSHOTS_VOLUME_PATH").unwrap_or_else(|_| {
-            self.snapshots_target_directory
-                .to_string_lossy()
-                .to_string()
-        });
-        let tezedge_host_path = env::var("TEZEDGE_VOLUME_PATH").unwrap_or_else(|_| {
-            self.snapshots_target_directory
-                .to_string_lossy()
-                .to_string()
-        });
+        let snapshot_host_path = self.snapshot_host_path();
+        let tezedge_host_path = self
+            .host_data_path
+            .clone()
+            .unwrap_or_else(|| self.database_directory.to_string_lossy().to_string());
         let host_config = HostConfig {
             mounts: Some(vec![Mount {
                 target: Some(
-                    self.snapshots_target_directory
+                    self.placement_directory
                         .to_string_lossy()
                         .to_string(),
                 ),
@@ -272,37 +1111,361 @@ impl TezedgeNodeController {
 
         let opts = CreateContainerOptions { name: cont_name.clone() };
 
-        docker
-            .create_container::<String, &str>(Some(opts), config)
-            .await?;
+        // a container left behind by a crashed process would make the creation conflict
+        trace!(self.log, "Docker remove_container"; "container" => &cont_name);
+        match docker.remove_container(&cont_name, None).await {
+            Ok(()) => warn!(self.log, "[Full] Removed the stale full snapshotting container {}", cont_name),
+            Err(bollard::errors::Error::DockerResponseNotFoundError { .. }) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        // the config holds no credentials, the entrypoint and the bind mounts are all there is to it
+        trace!(self.log, "Docker create_container"; "container" => &cont_name, "config" => format!("{:?}", config));
+        self.with_docker_retry("create full snapshot container", || {
+            docker.create_container::<String, &str>(Some(opts.clone()), config.clone())
+        })
+        .await?;
+
+        // steps 3 to 6 may fail midway, the container and the temp directory are cleaned up after them either way
+        let result: Result<u64, TezedgeNodeControllerError> = async {
+            info!(self.log, "[Full] Starting full snapshotting tezedge container (3/7)");
+            trace!(self.log, "Docker start_container"; "container" => &cont_name);
+            self.with_docker_retry("start full snapshot container", || docker.start_container::<String>(&cont_name, None))
+                .await?;
+
+            // a hung export would block the snapshot worker and every queued request behind it
+            let started = Instant::now();
+            while let Ok(true) = self.is_running(&cont_name).await {
+                if started.elapsed() >= Duration::from_secs(self.full_snapshot_timeout) {
+                    return Err(TezedgeNodeControllerError::FullSnapshotTimeout(
+                        cont_name.clone(),
+                        self.full_snapshot_timeout,
+                    ));
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            info!(self.log, "[Full] Full Snapshotting tezedge container finished (4/7)");
+
+            if self.clean_bootstrap_before_full {
+                let bootstrap_db = snapshot_path.join("bootstrap_db");
+                let removed = clean_bootstrap_db(&bootstrap_db)?;
+                info!(self.log, "[Full] Removed {} bytes of transient data from {}", removed, bootstrap_db.to_string_lossy());
+            }
+
+            self.set_stage(SnapshotStage::CreatingArchive);
+            let uncompressed_size = match self.format {
+                SnapshotFormat::Tar => {
+                    info!(self.log, "[Full] Creating tarball (5/7)");
+                    self.create_tezedge_tar_archive(
+                        &snapshot_name_temp,
+                        &snapshot_path,
+                        &full_snapshots_target_directory,
+                        self.node_layout.members(),
+                        &compression,
+                    )?
+                }
+                SnapshotFormat::Dir => {
+                    // the container already produced an uncompressed snapshot directory, just keep it
+                    info!(self.log, "[Full] Keeping the snapshot directory uncompressed (5/7)");
+                    fs::rename(&snapshot_path, full_snapshots_target_directory.join(&snapshot_name_temp))?;
+                    let size = snapshot_size(&full_snapshots_target_directory.join(&snapshot_name_temp))?;
+                    self.metrics.add_bytes_written(size);
+                    size
+                }
+                SnapshotFormat::Members => {
+                    info!(self.log, "[Full] Creating a tarball per database directory (5/7)");
+                    self.create_tezedge_member_archives(
+                        &snapshot_name_temp,
+                        &snapshot_path,
+                        &full_snapshots_target_directory,
+                        &compression,
+                    )?
+                }
+            };
+
+            // rename to the final name removing .temp indicating that the copy has been complete
+            self.set_stage(SnapshotStage::Finalizing);
+            info!(self.log, "[Full] Removing .temp from the snapshot directory (6/7)");
+            self.publish_snapshot(&full_snapshots_target_directory, &snapshot_name_temp, &full_snapshot_name)?;
+
+            Ok(uncompressed_size)
+        }
+        .await;
+
+        info!(self.log, "[Full] Removing Full Snapshotting tezedge container (7/7)");
+        trace!(self.log, "Docker remove_container"; "container" => &cont_name);
+        // forced, a container that timed out is still running
+        let remove_options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+        let removed = self
+            .with_docker_retry("remove full snapshot container", || {
+                docker.remove_container(&cont_name, Some(remove_options))
+            })
+            .await;
+        let snapshot_path_temp = full_snapshots_target_directory.join(&snapshot_name_temp);
+        let uncompressed_size = self.clean_up_full_snapshot(result, removed, &snapshot_path, &snapshot_path_temp)?;
 
-        info!(self.log, "[Full] Starting full snapshotting tezedge container (3/7)");
-        docker.start_container::<String>(&cont_name, None).await?;
+        Ok((full_snapshots_target_directory.join(&full_snapshot_name), uncompressed_size))
+    }
+
+    /// Removes the snapshot directory the container exported into and, after a failed step, the partial snapshot. The
+    /// error of a failed step is returned over any cleanup failure, which is only logged then
+    fn clean_up_full_snapshot(
+        &self,
+        result: Result<u64, TezedgeNodeControllerError>,
+        removed: Result<(), TezedgeNodeControllerError>,
+        snapshot_path: &Path,
+        snapshot_path_temp: &Path,
+    ) -> Result<u64, TezedgeNodeControllerError> {
+        let mut cleanup_errors = vec![];
+        if let Err(e) = removed {
+            cleanup_errors.push(e);
+        }
+        if snapshot_path.exists() {
+            if let Err(e) = fs_extra::remove_items(&[snapshot_path]) {
+                cleanup_errors.push(e.into());
+            }
+        }
+        if result.is_err() && snapshot_path_temp.exists() {
+            if let Err(e) = fs_extra::remove_items(&[snapshot_path_temp]) {
+                cleanup_errors.push(e.into());
+            }
+        }
 
-        while let Ok(true) = Self::is_running(&cont_name).await {
+        match (result, cleanup_errors.len()) {
+            (Ok(uncompressed_size), 0) => Ok(uncompressed_size),
+            (Ok(_), _) => Err(cleanup_errors.remove(0)),
+            (Err(e), _) => {
+                for cleanup_error in cleanup_errors {
+                    warn!(self.log, "[Full] Cleanup after the failed full snapshot failed: {}", cleanup_error);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes the context lock file left behind by the stopped node, node versions without one are fine
+    async fn remove_lock_file(&self) -> Result<(), TezedgeNodeControllerError> {
+        // the running checkpointed node still holds the lock
+        if self.skip_lock_removal || self.node_checkpointed {
+            return Ok(());
+        }
+
+        // give the stopped node the chance to release the lock itself, yanking it is the last resort
+        let lock_file = self.database_directory.join("context/index/lock");
+        let started = Instant::now();
+        while lock_file.exists() && started.elapsed() < Duration::from_secs(self.lock_release_timeout) {
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
-        info!(self.log, "[Full] Full Snapshotting tezedge container finished (4/7)");
 
-        info!(self.log, "[Full] Creating tarball (5/7)");
-        self.create_tezedge_tar_archive(&snapshot_name_temp, &snapshot_path, &full_snapshots_target_directory)?;
+        if lock_file.exists() {
+            // a lock touched after the stop points to a node still writing rather than an unclean shutdown
+            let modified = fs::metadata(&lock_file)?.modified()?;
+            warn!(
+                self.log,
+                "The context lock was not released within {}s, last modified at {}",
+                self.lock_release_timeout,
+                chrono::DateTime::<Utc>::from(modified).to_rfc3339()
+            );
+            if self.strict_lock {
+                return Err(TezedgeNodeControllerError::LockNotReleased(
+                    lock_file.to_string_lossy().to_string(),
+                ));
+            }
+            warn!(self.log, "Force removing the context lock");
+            fs_extra::remove_items(&[lock_file])?;
+        } else {
+            info!(self.log, "The context lock was released cleanly");
+        }
+        Ok(())
+    }
+
+    /// Renames the temp snapshot to its final name, syncing it first so a power loss cannot leave a complete looking but empty snapshot
+    fn publish_snapshot(&self, directory: &Path, temp_name: &str, final_name: &str) -> Result<(), std::io::Error> {
+        // the rename would silently replace an existing snapshot file
+        if directory.join(final_name).exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("Snapshot {} already exists", directory.join(final_name).to_string_lossy()),
+            ));
+        }
+        if self.fsync {
+            sync_path(&directory.join(temp_name))?;
+        }
+        fs::rename(directory.join(temp_name), directory.join(final_name))?;
+        if self.fsync {
+            // persists the rename itself
+            fs::File::open(directory)?.sync_all()?;
+        }
+        Ok(())
+    }
 
-        // rename to the final name removing .temp indicating that the copy has been complete
-        info!(self.log, "[Full] Removing .temp from the snapshot directory (6/7)");
-        fs::rename(
-            full_snapshots_target_directory.join(&snapshot_name_temp),
-            full_snapshots_target_directory.join(&snapshot_name),
-        )?;
+    /// Copies a finalized snapshot into the mirror directory, a failure only warns as the primary snapshot is complete
+    fn mirror_snapshot(&self, snapshot_path: &Path, kind: &str, snapshot_capacity: usize) {
+        if let Some(mirror_directory) = &self.mirror_directory {
+            let mirror_target_directory = mirror_directory.join(self.context_type.to_string()).join(kind);
+            let mirror_capacity = self.mirror_capacity.unwrap_or(snapshot_capacity);
 
-        info!(self.log, "[Full] Removing Full Snapshotting tezedge container (7/7)");
-        docker.remove_container(&cont_name, None).await?;
-        fs_extra::remove_items(&[snapshot_path])?;
+            info!(self.log, "Mirroring snapshot {} to {}", snapshot_path.to_string_lossy(), mirror_target_directory.to_string_lossy());
+            if let Err(e) = self.copy_to_mirror(snapshot_path, &mirror_target_directory, mirror_capacity) {
+                warn!(self.log, "Failed to mirror snapshot {}: {}", snapshot_path.to_string_lossy(), e);
+            }
+        }
+    }
 
+    /// Extracts the snapshot into the warm standby directory and points <dir>/<context>/<snapshot type> to it, then
+    /// removes the previous extraction. A failure only warns, the previous extraction is kept then
+    fn warm_standby(&self, snapshot_path: &Path, kind: &str, warm_standby_dir: &Path) {
+        // the full snapshot is the node's own export, it is imported rather than extracted
+        if kind != "archive" {
+            debug!(self.log, "Not extracting the {} snapshot {} into the warm standby directory", kind, snapshot_path.to_string_lossy());
+            return;
+        }
+        let standby_directory = warm_standby_dir.join(self.context_type.to_string());
+        info!(self.log, "Extracting snapshot {} into {}", snapshot_path.to_string_lossy(), standby_directory.to_string_lossy());
+        if let Err(e) = self.replace_standby(snapshot_path, kind, &standby_directory) {
+            warn!(self.log, "Failed to extract snapshot {} into the warm standby directory: {}", snapshot_path.to_string_lossy(), e);
+        }
+    }
+
+    fn replace_standby(&self, snapshot_path: &Path, kind: &str, standby_directory: &Path) -> Result<(), std::io::Error> {
+        let snapshot_name = snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        fs::create_dir_all(standby_directory)?;
+
+        let extraction_temp = standby_directory.join(manifest::temp_name(&snapshot_name, &self.temp_token));
+        let extraction = standby_directory.join(&snapshot_name);
+        for stale in [&extraction_temp, &extraction] {
+            if stale.exists() {
+                fs::remove_dir_all(stale)?;
+            }
+        }
+        if let Err(e) = unpack_snapshot(snapshot_path, &extraction_temp) {
+            let _ = fs::remove_dir_all(&extraction_temp);
+            return Err(e);
+        }
+        if self.fsync {
+            sync_path(&extraction_temp)?;
+        }
+        fs::rename(&extraction_temp, &extraction)?;
+
+        // a rename over a symlink replaces it atomically, a node started from the link sees either extraction whole
+        let link = standby_directory.join(kind);
+        let previous = fs::read_link(&link).ok().map(|previous| standby_directory.join(previous));
+        let link_temp = standby_directory.join(manifest::temp_name(kind, &self.temp_token));
+        std::os::unix::fs::symlink(&snapshot_name, &link_temp)?;
+        fs::rename(&link_temp, &link)?;
+
+        if let Some(previous) = previous.filter(|previous| *previous != extraction && previous.exists()) {
+            fs::remove_dir_all(previous)?;
+        }
+        Ok(())
+    }
+
+    fn copy_to_mirror(
+        &self,
+        snapshot_path: &Path,
+        mirror_target_directory: &Path,
+        mirror_capacity: usize,
+    ) -> Result<(), TezedgeNodeControllerError> {
+        if !mirror_target_directory.exists() {
+            dir::create_all(mirror_target_directory, false)?;
+        }
+
+        // the mirror rolls independently of the primary target
+        self.check_rolling(mirror_target_directory, mirror_capacity)?;
+
+        // the sidecars go first, the snapshot appearing under its final name marks the mirror copy complete
+        for sidecar in manifest::sidecars(snapshot_path) {
+            if let Some(sidecar_name) = sidecar.file_name() {
+                fs::copy(&sidecar, mirror_target_directory.join(sidecar_name))?;
+            }
+        }
+
+        for snapshot_file in manifest::snapshot_files(snapshot_path) {
+            let snapshot_name = snapshot_file.file_name().unwrap_or_default();
+            let snapshot_name_temp =
+                mirror_target_directory.join(manifest::temp_name(&snapshot_name.to_string_lossy(), &self.temp_token));
+            if snapshot_file.is_dir() {
+                copy_dir_reflink(&snapshot_file, &snapshot_name_temp)?;
+            } else {
+                reflink::reflink_or_copy(&snapshot_file, &snapshot_name_temp)?;
+            }
+            fs::rename(snapshot_name_temp, mirror_target_directory.join(snapshot_name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the stopped full snapshot and validation containers left behind by failed snapshots, returns their
+    /// names (only the candidates when dry_run is set)
+    pub async fn cleanup_containers(&self, dry_run: bool) -> Result<Vec<String>, TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+
+        // the name filter matches substrings, the prefixes are checked below
+        let mut filter = HashMap::new();
+        filter.insert(String::from("name"), vec![String::from("tezedge-snapshots-")]);
+        filter.insert(
+            String::from("status"),
+            vec![String::from("created"), String::from("exited"), String::from("dead")],
+        );
+        let containers = self
+            .with_docker_retry("list snapshot containers", || {
+                docker.list_containers(Some(ListContainersOptions {
+                    all: true,
+                    filters: filter.clone(),
+                    ..Default::default()
+                }))
+            })
+            .await?;
+
+        let mut removed = vec![];
+        for name in containers.into_iter().flat_map(|container| container.names.unwrap_or_default()) {
+            let name = name.trim_start_matches('/').to_string();
+            if !name.starts_with("tezedge-snapshots-full-") && !name.starts_with("tezedge-snapshots-validate-") {
+                continue;
+            }
+            if !dry_run {
+                info!(self.log, "Removing the orphaned container {}", name);
+                self.with_docker_retry("remove orphaned container", || docker.remove_container(&name, None))
+                    .await?;
+            }
+            removed.push(name);
+        }
+        Ok(removed)
+    }
+
+    /// Checks the node and monitoring containers exist among all the containers of the daemon, the names of the
+    /// existing containers are logged to spot a wrong name or suffix
+    pub async fn resolve_containers(&self) -> Result<(), TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+
+        let containers = self
+            .with_docker_retry("list containers", || {
+                docker.list_containers(Some(ListContainersOptions::<String> {
+                    all: true,
+                    ..Default::default()
+                }))
+            })
+            .await?;
+        let existing: Vec<String> = containers
+            .into_iter()
+            .flat_map(|container| container.names.unwrap_or_default())
+            .map(|name| name.trim_start_matches('/').to_string())
+            .collect();
+
+        for container_name in [&self.node_container_name, &self.monitoring_container_name] {
+            if !existing.contains(container_name) {
+                info!(self.log, "Existing containers: {}", existing.join(", "));
+                return Err(TezedgeNodeControllerError::ContainerNotFound(container_name.clone()));
+            }
+        }
         Ok(())
     }
 
-    async fn is_running(container_name: &str) -> Result<bool, TezedgeNodeControllerError> {
-        let docker = Docker::connect_with_socket_defaults()?;
+    async fn is_running(&self, container_name: &str) -> Result<bool, TezedgeNodeControllerError> {
+        let docker = self.docker()?;
 
         let mut filter = HashMap::new();
         filter.insert(
@@ -313,6 +1476,7 @@ impl TezedgeNodeController {
             String::from("status"),
             vec![String::from("running")],
         );
+        trace!(self.log, "Docker list_containers"; "filters" => format!("{:?}", filter));
         let containers = &docker
             .list_containers(Some(ListContainersOptions {
                 all: true,
@@ -320,6 +1484,7 @@ impl TezedgeNodeController {
                 ..Default::default()
             }))
             .await?;
+        trace!(self.log, "Docker list_containers response"; "containers" => containers.len());
 
         if containers.is_empty() {
             Ok(false)
@@ -329,111 +1494,2092 @@ impl TezedgeNodeController {
     }
 
     fn check_rolling(&self, snapshot_dir: &Path, snapshot_capacity: usize) -> Result<(), TezedgeNodeControllerError> {
-        // identify and remove the oldest snapshot in the target dir, if we are over capacity
-        let current_snapshots = dir::get_dir_content(&snapshot_dir)?
-            .files
-            .iter()
-            .map(|file| snapshot_dir.join(file))
-            // we need the only the direct files contained in the main directory, filter out all deeper sub directories
-            .filter(|p| {
-                p.components().count() == snapshot_dir.components().count() + 1
-            })
-            .collect::<Vec<PathBuf>>();
-
-        // collect all last_modified times
-        let mut dir_times: Vec<(PathBuf, FileTime)> = vec![];
-        for snapshot_path in current_snapshots {
-            let meta = fs::metadata(&snapshot_path)?;
-            let last_modified = FileTime::from_last_modification_time(&meta);
-            dir_times.push((snapshot_path, last_modified));
+        // the retention is managed externally, only report the growth
+        if self.no_roll {
+            let over_capacity = self.rolling_candidates(snapshot_dir, snapshot_capacity.saturating_sub(1))?.len();
+            if over_capacity > 0 {
+                warn!(
+                    self.log,
+                    "Rolling disabled - {} holds {} snapshot(s) over its capacity of {}",
+                    snapshot_dir.to_string_lossy(),
+                    over_capacity,
+                    snapshot_capacity
+                );
+            }
+            return Ok(());
         }
 
-        // sort by times
-        dir_times.sort_by(|a, b| a.1.cmp(&b.1));
+        // identify and remove the oldest snapshots in the target dir, making room for the new one
+        for oldest in self.rolling_candidates(snapshot_dir, snapshot_capacity.saturating_sub(1))? {
+            info!(self.log, "Rolling snapshots - Removing oldest snapshot {}", oldest.to_string_lossy());
+            let size = snapshot_size(&oldest)?;
+            remove_snapshot(&oldest)?;
+            self.metrics.add_bytes_rolled(size);
+        }
 
-        // remove the oldest file if over capacity
-        if dir_times.len() >= snapshot_capacity {
-            info!(self.log, "Rolling snapshots - Removing oldest snapshot");
-            fs_extra::remove_items(&[dir_times[0].0.clone()])?;
+        if let Some(max_total_size) = self.max_total_size {
+            self.roll_to_size_budget(snapshot_dir, max_total_size)?;
         }
         Ok(())
     }
 
-    /// Takes a snapshot of the tezedge node
-    pub async fn take_snapshot(
-        &mut self,
-        snapshot_capacity: usize,
+    /// Removes the oldest snapshots until the directory, including room for the new snapshot, fits the budget
+    fn roll_to_size_budget(&self, snapshot_dir: &Path, max_total_size: u64) -> Result<(), TezedgeNodeControllerError> {
+        // all snapshots, oldest first
+        let mut snapshots = self.rolling_candidates(snapshot_dir, 0)?;
+        let mut sizes = snapshots
+            .iter()
+            .map(|snapshot| snapshot_size(snapshot))
+            .collect::<Result<Vec<u64>, _>>()?;
+
+        // the newest snapshot is the best estimate of the size of the upcoming one
+        let expected_size = sizes.last().copied().unwrap_or(0);
+        let mut total_size: u64 = sizes.iter().sum();
+
+        while total_size + expected_size > max_total_size && !snapshots.is_empty() {
+            let oldest = snapshots.remove(0);
+            let size = sizes.remove(0);
+            remove_snapshot(&oldest)?;
+            self.metrics.add_bytes_rolled(size);
+            total_size -= size;
+            info!(
+                self.log,
+                "Size budget - Removed oldest snapshot {} ({} bytes), total is now {} bytes",
+                oldest.to_string_lossy(),
+                size,
+                total_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the oldest snapshots in the directory that have to go so that only `keep` snapshots remain
+    fn rolling_candidates(&self, snapshot_dir: &Path, keep: usize) -> Result<Vec<PathBuf>, TezedgeNodeControllerError> {
+        // we need only the direct entries of the main directory, archives are files, dir snapshots are directories
+        let mut current_snapshots = fs::read_dir(snapshot_dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+
+        // sidecars are removed together with their snapshot, the latest pointer is never rolled
+        current_snapshots.retain(|p| !manifest::is_sidecar(p) && !p.ends_with(manifest::LATEST_FILE_NAME));
+
+        // a snapshot another run is still writing is left alone, leftovers of dead runs are rolled like snapshots
+        current_snapshots.retain(|p| !manifest::is_live_temp(p));
+
+        // the parts of a split archive are one snapshot
+        current_snapshots = current_snapshots
+            .into_iter()
+            .map(|p| manifest::part_of(&p).unwrap_or(p))
+            .collect();
+        current_snapshots.sort();
+        current_snapshots.dedup();
+
+        // with a suffix, only roll the snapshots of the same environment
+        if let Some(suffix) = &self.name_suffix {
+            let suffix_tag = format!("_{}.", suffix);
+            current_snapshots.retain(|p| {
+                p.file_name()
+                    .map(|name| name.to_string_lossy().contains(&suffix_tag))
+                    .unwrap_or(false)
+            });
+        }
+
+        // collect all last_modified times
+        let mut dir_times: Vec<(PathBuf, FileTime)> = vec![];
+        for snapshot_path in current_snapshots {
+            let meta = match manifest::snapshot_files(&snapshot_path).first() {
+                Some(snapshot_file) => fs::metadata(snapshot_file)?,
+                None => continue,
+            };
+            let last_modified = FileTime::from_last_modification_time(&meta);
+            dir_times.push((snapshot_path, last_modified));
+        }
+
+        // sort by times
+        dir_times.sort_by_key(|a| a.1);
+
+        let over_capacity = dir_times.len().saturating_sub(keep);
+        Ok(dir_times
+            .into_iter()
+            .take(over_capacity)
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// Applies the retention to all snapshot directories right away, returns the removed snapshots
+    /// (only the candidates when dry_run is set)
+    pub fn prune(&self, snapshot_capacity: &SnapshotCapacity, dry_run: bool) -> Result<Vec<PathBuf>, TezedgeNodeControllerError> {
+        let mut directories = vec![];
+        for kind in ["archive", "full"] {
+            for target_directory in self.target_directories() {
+                directories.push((
+                    target_directory.join(self.context_type.to_string()).join(kind),
+                    snapshot_capacity.of(kind),
+                ));
+            }
+            if let Some(mirror_directory) = &self.mirror_directory {
+                directories.push((
+                    mirror_directory.join(self.context_type.to_string()).join(kind),
+                    self.mirror_capacity.unwrap_or_else(|| snapshot_capacity.of(kind)),
+                ));
+            }
+        }
+
+        let mut pruned = vec![];
+        for (directory, capacity) in directories {
+            if !directory.exists() {
+                continue;
+            }
+            let candidates = self.rolling_candidates(&directory, capacity)?;
+            if !dry_run {
+                for candidate in &candidates {
+                    info!(self.log, "Pruning snapshot {}", candidate.to_string_lossy());
+                    remove_snapshot(candidate)?;
+                }
+            }
+            pruned.extend(candidates);
+        }
+        Ok(pruned)
+    }
+
+    /// Uncompressed sizes of the database directories a snapshot is made of, measured on the running node
+    pub fn estimate(&self) -> Result<Vec<(String, u64)>, TezedgeNodeControllerError> {
+        self.check_node_layout()?;
+        self.node_layout
+            .members()
+            .iter()
+            .map(|member| Ok((member.to_string(), dir::get_size(self.database_directory.join(member))?)))
+            .collect()
+    }
+
+    /// Takes a snapshot of the tezedge node, a failure is published as an event
+    pub async fn take_snapshot(
+        &mut self,
+        snapshot_capacity: &SnapshotCapacity,
         snapshot_type: &SnapshotType,
     ) -> Result<(), TezedgeNodeControllerError> {
-        self.last_snapshot_timestamp = Some(Instant::now());
-        let head_block_hash = self.get_head().await?.hash;
-
-        // get the time for the snapshot title
-        let now = Utc::now().naive_utc();
-        let date = now.date().to_string().replace('-', "");
-        let time: String = now
-            .time()
-            .to_string()
-            .replace(':', "")
-            .split('.')
-            .take(1)
-            .collect();
+        let result = self.run_snapshot(snapshot_capacity, snapshot_type).await;
+        if let Err(e) = &result {
+            let metadata = serde_json::json!({
+                "network": self.network,
+                "context": self.context_type.to_string(),
+                "stage": self.stage.to_string(),
+                "error": e.to_string(),
+            });
+            self.events.publish("snapshot_failed", metadata).await;
+        }
+        result
+    }
 
-        let snapshot_name = format!(
-            "{}_{}_{}-{}_{}_{}",
-            "tezedge", self.network, date, time, head_block_hash, self.context_type.to_string()
-        );
+    async fn run_snapshot(
+        &mut self,
+        snapshot_capacity: &SnapshotCapacity,
+        snapshot_type: &SnapshotType,
+    ) -> Result<(), TezedgeNodeControllerError> {
+        let started = Instant::now();
+        self.last_snapshot_timestamp = Some(started);
+        self.temp_token = manifest::temp_token(Utc::now().timestamp_millis());
+        // a failed snapshot leaves its stage behind, the time since is not part of any stage
+        self.stage = SnapshotStage::Idle;
+        self.stage_durations.clear();
+        self.set_stage(SnapshotStage::CheckingHead);
+        // nothing is started before the checks pass, a failed check leaves the controller idle
+        let TezosBlockHeader {
+            hash: head_block_hash,
+            level: head_level,
+        } = match self.get_head().await {
+            Ok(head) => head,
+            Err(e) => {
+                self.set_stage(SnapshotStage::Idle);
+                return Err(e);
+            }
+        };
 
-        // 1. stop the node container
-        info!(self.log, "Stopping tezedge container");
-        self.stop().await?;
+        // a stalled head (or a reorg back to it) would produce an identical snapshot
+        if !self.allow_same_head && self.last_snapshot_hash.as_ref() == Some(&head_block_hash) {
+            info!(self.log, "Head {} was already snapshotted, skipping", head_block_hash);
+            self.set_stage(SnapshotStage::Idle);
+            return Ok(());
+        }
 
-        match snapshot_type {
-            SnapshotType::Archive => {
-                self.take_archive_snapshot(snapshot_capacity, &snapshot_name).await?;
-            },
-            SnapshotType::Full => {
-                self.take_full_snapshot(&snapshot_name, snapshot_capacity).await?;
-            },
-            SnapshotType::All => {
-                self.take_archive_snapshot(snapshot_capacity, &snapshot_name).await?;
-                self.take_full_snapshot(&snapshot_name, snapshot_capacity).await?;
-            },
+        // a process that died midway through the same head only redoes the sub-steps it did not complete
+        let mut progress = match self.read_progress(&head_block_hash) {
+            Some(progress) => {
+                info!(
+                    self.log,
+                    "Resuming snapshot {}, already completed: {:?}",
+                    progress.snapshot_name,
+                    progress.completed.iter().map(|(kind, _, _)| kind).collect::<Vec<_>>()
+                );
+                progress
+            }
+            None => {
+                // get the time for the snapshot title, structured outputs get the full RFC3339 timestamp instead
+                let snapshot_time = Utc::now();
+                let timestamp = snapshot_time.format(&self.name_timestamp_format);
+                let base_name = |counter: usize| {
+                    let counted = |name: String| match counter {
+                        0 => name,
+                        _ => format!("{}.{}", name, counter),
+                    };
+                    match self.naming_scheme {
+                        NamingScheme::Tezedge => format!(
+                            "{}_{}_{}_{}_{}",
+                            "tezedge",
+                            self.network,
+                            counted(timestamp.to_string()),
+                            head_block_hash,
+                            self.context_type
+                        ),
+                        NamingScheme::Octez => counted(format!("{}-{}-{}", self.network, head_block_hash, head_level)),
+                    }
+                };
+
+                // two snapshots of the same head within the same timestamp would clobber each other, count them up
+                let mut snapshot_name = base_name(0);
+                let mut counter = 0;
+                while self.snapshot_name_taken(&snapshot_name) {
+                    counter += 1;
+                    snapshot_name = base_name(counter);
+                }
+                // the label goes before the suffix, rolling matches the suffix right before the extension
+                if let (Some(label), true) = (&self.label, self.label_in_name) {
+                    snapshot_name = format!("{}_{}", snapshot_name, label);
+                }
+                if let Some(suffix) = &self.name_suffix {
+                    snapshot_name = format!("{}_{}", snapshot_name, suffix);
+                }
+
+                SnapshotProgress {
+                    block_hash: head_block_hash.clone(),
+                    snapshot_name,
+                    created_at: snapshot_time.to_rfc3339(),
+                    completed: vec![],
+                }
+            }
+        };
+        let snapshot_name = progress.snapshot_name.clone();
+
+        if let Err(e) = self.check_target() {
+            self.set_stage(SnapshotStage::Idle);
+            return Err(e);
         }
 
-        // 6. start the node container back up
-        info!(self.log, "Starting back up the tezedge container");
-        self.start().await?;
+        if let Ok(mut status) = self.status.write() {
+            status.snapshot_name = Some(snapshot_name.clone());
+            status.started_at = Some(progress.created_at.clone());
+        }
+
+        let metadata = serde_json::json!({
+            "name": snapshot_name,
+            "network": self.network,
+            "context": self.context_type.to_string(),
+            "block_hash": head_block_hash,
+            "level": head_level,
+        });
+        self.events.publish("snapshot_started", metadata).await;
+
+        let (take_archive, take_full) = match snapshot_type {
+            SnapshotType::Archive => (true, false),
+            SnapshotType::Full => (false, true),
+            SnapshotType::All => (true, true),
+        };
+        let is_completed = |kind: &str| progress.completed.iter().any(|(completed, _, _)| completed == kind);
+        let (archive_completed, full_completed) = (is_completed("archive"), is_completed("full"));
+        // only the post-processing is left when the process died after the last sub-step
+        let mut node_needed = (take_archive && !archive_completed) || (take_full && !full_completed);
+
+        // a node checkpointing its context to a consistent on-disk state can stay up, the full snapshot still needs it stopped
+        self.node_checkpointed = false;
+        if let (true, false, Some(checkpoint_rpc_path)) = (node_needed, take_full && !full_completed, self.checkpoint_rpc_path.clone()) {
+            match self.checkpoint(&checkpoint_rpc_path).await {
+                Ok(()) => {
+                    info!(self.log, "Node checkpointed its context, snapshotting without stopping it");
+                    self.node_checkpointed = true;
+                    node_needed = false;
+                }
+                Err(e) => warn!(self.log, "Checkpoint failed, falling back to stopping the node: {}", e),
+            }
+        }
+
+        // a layout not matching the node version would only fail after the node is already stopped
+        if take_archive && !archive_completed {
+            if let Err(e) = self.check_node_layout() {
+                self.set_stage(SnapshotStage::Idle);
+                return Err(e);
+            }
+        }
+
+        // sampling the live database keeps the calibration out of the downtime
+        if let (Some(_), None) = (self.compression_time_budget, &self.external_compressor) {
+            let kinds: Vec<&str> = [("archive", take_archive && !archive_completed), ("full", take_full && !full_completed)]
+                .iter()
+                .filter(|(_, pending)| *pending)
+                .map(|(kind, _)| *kind)
+                .collect();
+            self.calibrate_compression(&kinds);
+        }
+
+        let stopped_at = Instant::now();
+        if node_needed {
+            self.set_stage(SnapshotStage::StoppingNode);
+            // 1. stop the node container
+            info!(self.log, "Stopping tezedge container");
+            self.stop().await?;
+
+            // some storage backends flush and release the files only a while after the container exited
+            if self.post_stop_delay > 0 {
+                info!(self.log, "Waiting {}s before reading the database", self.post_stop_delay);
+                tokio::time::sleep(Duration::from_secs(self.post_stop_delay)).await;
+            }
+        }
+
+        // attempt each type independently, a failure in one should not prevent the other
+        let mut errors = vec![];
+        if let (true, false, Some(output_pipe)) = (take_archive, archive_completed, self.output_pipe.clone()) {
+            // nothing lands in the target directory, there is nothing to roll, rename or post-process
+            match self.stream_archive_snapshot(&output_pipe).await {
+                Ok(uncompressed_size) => info!(
+                    self.log,
+                    "[Archive] Streamed {} uncompressed bytes into {}",
+                    uncompressed_size,
+                    output_pipe.to_string_lossy()
+                ),
+                Err(e) => {
+                    error!(self.log, "[Archive] Snapshot failed: {}", e);
+                    errors.push(e);
+                }
+            }
+        } else if take_archive && !archive_completed {
+            match self.take_archive_snapshot(snapshot_capacity.archive, &snapshot_name).await {
+                Ok((path, uncompressed_size)) => {
+                    progress.completed.push(("archive".to_string(), path, uncompressed_size));
+                    self.write_progress(&progress);
+                }
+                Err(e) => {
+                    error!(self.log, "[Archive] Snapshot failed: {}", e);
+                    errors.push(e);
+                }
+            }
+        }
+        if take_full && !full_completed {
+            match self.take_full_snapshot(&snapshot_name, snapshot_capacity.full).await {
+                Ok((path, uncompressed_size)) => {
+                    progress.completed.push(("full".to_string(), path, uncompressed_size));
+                    self.write_progress(&progress);
+                }
+                Err(e) => {
+                    error!(self.log, "[Full] Snapshot failed: {}", e);
+                    errors.push(e);
+                }
+            }
+        }
+
+        let created_at = progress.created_at.clone();
+        let snapshots = progress.completed;
+        for (kind, snapshot_path, uncompressed_size) in &snapshots {
+            let manifest = SnapshotManifest {
+                name: snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                snapshot_type: kind.to_string(),
+                context_type: self.context_type.to_string(),
+                network: self.network.clone(),
+                block_hash: head_block_hash.clone(),
+                level: Some(head_level),
+                created_at: created_at.clone(),
+                validated: false,
+                checksum_algorithm: None,
+                checksum: None,
+                label: self.label.clone(),
+                uncompressed_size_bytes: Some(*uncompressed_size),
+                parts: None,
+                members: match self.format {
+                    SnapshotFormat::Members => Some(
+                        self.node_layout
+                            .members()
+                            .iter()
+                            .map(|member| self.member_archive_name(member, &self.compression_of(kind)))
+                            .collect(),
+                    ),
+                    _ => None,
+                },
+                magnet: None,
+            };
+            if let Err(e) = manifest.write(snapshot_path) {
+                errors.push(e.into());
+            }
+        }
+
+        let mut result = match errors.len() {
+            0 => Ok(()),
+            1 if !take_archive || !take_full => Err(errors.remove(0)),
+            _ => Err(TezedgeNodeControllerError::SnapshotsFailed(errors)),
+        };
+
+        // keep the stage the snapshot failed in for the diagnostics
+        let mut snapshot_stage = self.stage;
+
+        if node_needed {
+            self.set_stage(SnapshotStage::StartingNode);
+            // 6. start the node container back up, even if the snapshot failed
+            info!(self.log, "Starting back up the tezedge container");
+            match self.start_with_cooldown().await {
+                Ok(()) => {
+                    if let Some(recovered_head) = self.wait_for_recovery(stopped_at).await {
+                        if !snapshots.is_empty() {
+                            self.check_recorded_head(&snapshots, &head_block_hash, head_level, &recovered_head);
+                        }
+                    }
+                }
+                // reported together with the snapshot errors, the finished snapshots are still post-processed
+                Err(e) => {
+                    error!(self.log, "Failed to start back up the tezedge container: {}", e);
+                    result = match result {
+                        Ok(()) => {
+                            snapshot_stage = SnapshotStage::StartingNode;
+                            Err(e)
+                        }
+                        Err(TezedgeNodeControllerError::SnapshotsFailed(mut errors)) => {
+                            errors.push(e);
+                            Err(TezedgeNodeControllerError::SnapshotsFailed(errors))
+                        }
+                        Err(snapshot_error) => Err(TezedgeNodeControllerError::SnapshotsFailed(vec![snapshot_error, e])),
+                    };
+                }
+            }
+        }
+
+        // hashing large archives takes a while, it is done after the node is back up
+        for (kind, snapshot_path, _) in &snapshots {
+            self.set_stage(SnapshotStage::PostProcessing);
+            if let Some(algorithm) = &self.checksum_algorithm {
+                self.checksum(snapshot_path, algorithm);
+            }
+            // the full snapshot is the node's own export, only the archive can be booted as a data directory
+            if self.validate_snapshot && kind == "archive" {
+                self.validate(snapshot_path).await;
+                self.set_stage(SnapshotStage::PostProcessing);
+            }
+            // before the split, the parts can not be extracted
+            if let Some(warm_standby_dir) = &self.warm_standby_dir {
+                self.warm_standby(snapshot_path, kind, warm_standby_dir);
+            }
+            if let Some(split_size) = self.split_size {
+                self.split(snapshot_path, split_size);
+            }
+            if !self.torrent_trackers.is_empty() || !self.torrent_web_seeds.is_empty() {
+                self.write_torrent(snapshot_path);
+            }
+            // rolling orders by mtime, copies and renames across filesystems do not keep the creation order reliably
+            self.set_snapshot_time(snapshot_path, &created_at);
+            // after the sidecars are final, they are rewritten by the steps above
+            if let Some((uid, gid)) = self.chown {
+                self.chown_snapshot(snapshot_path, uid, gid);
+            }
+            self.mirror_snapshot(snapshot_path, kind, snapshot_capacity.of(kind));
+            self.update_latest(snapshot_path, head_level);
 
+            info!(
+                self.log,
+                "Snapshot completed";
+                "name" => snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                "block_hash" => &head_block_hash,
+                "level" => head_level,
+                "type" => kind,
+                "context" => self.context_type.to_string(),
+                "size_bytes" => snapshot_size(snapshot_path).unwrap_or_default(),
+                "duration_secs" => started.elapsed().as_secs(),
+            );
+
+            let metadata = serde_json::json!({
+                "name": snapshot_path.file_name().unwrap_or_default().to_string_lossy(),
+                "type": kind,
+                "network": self.network,
+                "context": self.context_type.to_string(),
+                "block_hash": head_block_hash,
+                "level": head_level,
+                "size_bytes": snapshot_size(snapshot_path).unwrap_or_default(),
+                "duration_secs": started.elapsed().as_secs(),
+            });
+            self.events.publish("snapshot_succeeded", metadata).await;
+        }
+
+        if result.is_ok() {
+            if let Ok(mut health) = self.health.write() {
+                health.last_success = Some(std::time::Instant::now());
+            }
+            self.remove_progress();
+            self.record_duration(started.elapsed().as_secs());
+            self.last_snapshot_hash = Some(head_block_hash);
+            self.set_stage(SnapshotStage::Idle);
+        } else {
+            self.set_stage(snapshot_stage);
+        }
+
+        let secs = |stage| self.stage_durations.get(&stage).map(Duration::as_secs).unwrap_or_default();
+        info!(
+            self.log,
+            "Snapshot stage durations";
+            "stop_secs" => secs(SnapshotStage::StoppingNode),
+            "roll_secs" => secs(SnapshotStage::Rolling),
+            "container_run_secs" => secs(SnapshotStage::RunningSnapshotContainer),
+            "tar_secs" => secs(SnapshotStage::CreatingArchive),
+            "rename_secs" => secs(SnapshotStage::Finalizing),
+            "start_secs" => secs(SnapshotStage::StartingNode),
+            "recovery_secs" => secs(SnapshotStage::WaitingForRecovery),
+            "validation_secs" => secs(SnapshotStage::ValidatingSnapshot),
+            "post_processing_secs" => secs(SnapshotStage::PostProcessing),
+        );
+
+        result
+    }
+
+    /// Places the snapshot and checks its target directory can take it
+    fn check_target(&mut self) -> Result<(), TezedgeNodeControllerError> {
+        self.placement_directory = self.place_snapshot();
+
+        // a read-only target would only fail after the node is already stopped
+        self.check_target_writable()?;
+
+        // never stop the node when the target is already below the free space floor
+        let available = fs2::available_space(&self.placement_directory)?;
+        if available < self.min_free_space {
+            return Err(TezedgeNodeControllerError::InsufficientFreeSpace {
+                available,
+                required: self.min_free_space,
+            });
+        }
         Ok(())
     }
 
-    pub async fn can_snapshot(&self, snapshot_frequency: u64) -> bool {
-        match self.get_head().await {
-            Ok(_) => {
-                if let Some(instant) = self.last_snapshot_timestamp {
-                    instant.elapsed() >= Duration::from_secs(snapshot_frequency)
-                } else {
-                    true
+    /// The primary target directory followed by the additional ones
+    fn target_directories(&self) -> Vec<PathBuf> {
+        std::iter::once(self.snapshots_target_directory.clone())
+            .chain(self.additional_target_directories.iter().cloned())
+            .collect()
+    }
+
+    /// The target directory with the most free space, the new snapshot is placed there
+    fn place_snapshot(&self) -> PathBuf {
+        if self.additional_target_directories.is_empty() {
+            return self.snapshots_target_directory.clone();
+        }
+
+        let placement_directory = self
+            .target_directories()
+            .into_iter()
+            .filter_map(|directory| match fs2::available_space(&directory) {
+                Ok(available) => Some((directory, available)),
+                Err(e) => {
+                    warn!(self.log, "Failed to get the free space of {}: {}", directory.to_string_lossy(), e);
+                    None
                 }
+            })
+            .max_by_key(|(_, available)| *available)
+            .map(|(directory, _)| directory)
+            .unwrap_or_else(|| self.snapshots_target_directory.clone());
+        info!(self.log, "Placing the snapshot in {}", placement_directory.to_string_lossy());
+        placement_directory
+    }
+
+    /// Whether any file of a snapshot with this base name, finished or not, is in the target directory
+    fn snapshot_name_taken(&self, snapshot_name: &str) -> bool {
+        let prefix = format!("{}.", snapshot_name);
+        self.target_directories().iter().any(|target_directory| {
+            ["archive", "full"].iter().any(|kind| {
+                fs::read_dir(target_directory.join(self.context_type.to_string()).join(kind))
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            })
+        })
+    }
+
+    /// Appends the duration to the history and persists it, so the trend survives restarts
+    fn record_duration(&self, secs: u64) {
+        let state = match self.state.write() {
+            Ok(mut state) => {
+                state.record_duration(secs);
+                state.clone()
             }
-            Err(_) => {
-                // if the node does not respond to the rpc, do not snapshot
-                // this catches a corner-case where, the node is started with a cleaned up DB
-                // and is not yet ready for the first snapshot
-                false
+            Err(_) => return,
+        };
+        if let Err(e) = state.write(&self.snapshots_target_directory) {
+            warn!(self.log, "Failed to write the snapshot state: {}", e);
+        }
+    }
+
+    /// Logs the statistics of the recent snapshot durations, at most once per DURATION_STATS_LOG_INTERVAL
+    pub fn log_duration_stats(&mut self) {
+        if matches!(self.last_duration_stats_log, Some(logged) if logged.elapsed() < DURATION_STATS_LOG_INTERVAL) {
+            return;
+        }
+        self.last_duration_stats_log = Some(Instant::now());
+
+        if let Some(stats) = self.state.read().ok().and_then(|state| state.duration_stats()) {
+            info!(
+                self.log,
+                "Snapshot duration statistics";
+                "count" => stats.count,
+                "min_secs" => stats.min_secs,
+                "max_secs" => stats.max_secs,
+                "avg_secs" => stats.avg_secs,
+                "p95_secs" => stats.p95_secs,
+            );
+        }
+    }
+
+    fn progress_path(&self) -> PathBuf {
+        self.snapshots_target_directory
+            .join(format!(".snapshot-progress-{}.json", self.context_type))
+    }
+
+    /// Reads the progress marker, a marker of a different head or with missing snapshots is stale
+    fn read_progress(&self, head_block_hash: &str) -> Option<SnapshotProgress> {
+        let mut progress: SnapshotProgress = fs::read(self.progress_path())
+            .ok()
+            .and_then(|progress| serde_json::from_slice(&progress).ok())?;
+        if progress.block_hash != head_block_hash {
+            self.remove_progress();
+            return None;
+        }
+        progress.completed.retain(|(_, path, _)| !manifest::snapshot_files(path).is_empty());
+        Some(progress)
+    }
+
+    fn write_progress(&self, progress: &SnapshotProgress) {
+        let result = serde_json::to_vec_pretty(progress)
+            .map_err(std::io::Error::from)
+            .and_then(|progress| fs::write(self.progress_path(), progress));
+        if let Err(e) = result {
+            warn!(self.log, "Failed to write the snapshot progress marker: {}", e);
+        }
+    }
+
+    fn remove_progress(&self) {
+        let progress_path = self.progress_path();
+        if progress_path.exists() {
+            if let Err(e) = fs::remove_file(&progress_path) {
+                warn!(self.log, "Failed to remove the snapshot progress marker: {}", e);
+            }
+        }
+    }
+
+    /// Points the latest.json of the snapshot's directory to it
+    fn update_latest(&self, snapshot_path: &Path, level: i32) {
+        let result = SnapshotManifest::read(snapshot_path).and_then(|manifest| {
+            let latest = LatestSnapshot {
+                name: manifest.name,
+                size_bytes: snapshot_size(snapshot_path).unwrap_or_default(),
+                block_hash: manifest.block_hash,
+                level,
+                created_at: manifest.created_at,
+                parts: manifest.parts,
+                members: manifest.members,
+            };
+            latest.write(snapshot_path.parent().unwrap_or_else(|| Path::new(".")))
+        });
+        if let Err(e) = result {
+            warn!(self.log, "Failed to update {} for {}: {}", manifest::LATEST_FILE_NAME, snapshot_path.to_string_lossy(), e);
+        }
+    }
+
+    /// Sets the mtime of the snapshot files to the snapshot creation time
+    fn set_snapshot_time(&self, snapshot_path: &Path, created_at: &str) {
+        let created_at = match chrono::DateTime::parse_from_rfc3339(created_at) {
+            Ok(created_at) => created_at,
+            Err(e) => {
+                warn!(self.log, "Failed to parse the creation time {}: {}", created_at, e);
+                return;
+            }
+        };
+        let mtime = FileTime::from_unix_time(created_at.timestamp(), created_at.timestamp_subsec_nanos());
+        for path in manifest::snapshot_files(snapshot_path) {
+            if let Err(e) = filetime::set_file_mtime(&path, mtime) {
+                warn!(self.log, "Failed to set the mtime of {}: {}", path.to_string_lossy(), e);
+            }
+        }
+    }
+
+    /// Splits an archive into numbered parts of split_size bytes and lists them in its manifest
+    fn split(&self, snapshot_path: &Path, split_size: u64) {
+        // a directory snapshot consists of many files already
+        if snapshot_path.is_dir() || fs::metadata(snapshot_path).map(|m| m.len() <= split_size).unwrap_or(true) {
+            return;
+        }
+
+        info!(self.log, "Splitting {} into parts of {} bytes", snapshot_path.to_string_lossy(), split_size);
+        let result = split_file(snapshot_path, split_size).and_then(|parts| {
+            let mut manifest = SnapshotManifest::read(snapshot_path)?;
+            manifest.parts = Some(
+                parts
+                    .iter()
+                    .map(|part| part.file_name().unwrap_or_default().to_string_lossy().to_string())
+                    .collect(),
+            );
+            manifest.write(snapshot_path)?;
+            // the parts replace the archive only once they are complete
+            fs::remove_file(snapshot_path)
+        });
+        if let Err(e) = result {
+            error!(self.log, "Failed to split {}: {}", snapshot_path.to_string_lossy(), e);
+            for part in manifest::parts(snapshot_path) {
+                let _ = fs::remove_file(part);
             }
         }
     }
-    fn create_tezedge_tar_archive(&self, archive_name: &str, source: &Path, destination: &Path) -> Result<(), std::io::Error> {
-        let tar_gz = std::fs::File::create(destination.join(archive_name))?;
-        let enc = GzEncoder::new(tar_gz, Compression::fast());
-        let mut tar = tar::Builder::new(enc);
-        crit!(self.log, "Adding to archive: {}", source.join("context").to_string_lossy());
-        tar.append_dir_all("context", source.join("context"))?;
-        crit!(self.log, "Adding to archive: {}", source.join("bootstrap_db").to_string_lossy());
-        tar.append_dir_all("bootstrap_db", source.join("bootstrap_db"))?;
-        tar.finish()?;
+
+    /// Hands the snapshot, its sidecars and its type and context directories over to the configured owner
+    fn chown_snapshot(&self, snapshot_path: &Path, uid: u32, gid: u32) {
+        let mut paths = vec![];
+        if let Some(type_directory) = snapshot_path.parent() {
+            paths.extend(type_directory.parent().map(Path::to_path_buf));
+            paths.push(type_directory.to_path_buf());
+        }
+        paths.extend(manifest::sidecars(snapshot_path));
+
+        let result = paths
+            .iter()
+            .try_for_each(|path| std::os::unix::fs::chown(path, Some(uid), Some(gid)))
+            .and_then(|_| {
+                manifest::snapshot_files(snapshot_path)
+                    .iter()
+                    .try_for_each(|snapshot_file| chown_recursive(snapshot_file, uid, gid))
+            });
+        if let Err(e) = result {
+            warn!(self.log, "Failed to chown {} to {}:{}: {}", snapshot_path.to_string_lossy(), uid, gid, e);
+        }
+    }
+
+    fn check_node_layout(&self) -> Result<(), TezedgeNodeControllerError> {
+        for member in self.node_layout.members() {
+            let member_path = self.database_directory.join(member);
+            if !member_path.is_dir() {
+                return Err(TezedgeNodeControllerError::NodeLayoutMismatch(
+                    self.node_layout.to_string(),
+                    member_path.to_string_lossy().to_string(),
+                ));
+            }
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Writes and removes a tiny probe file in the snapshot target
+    fn check_target_writable(&self) -> Result<(), TezedgeNodeControllerError> {
+        let probe_path = self.placement_directory.join(".write-probe.temp");
+        fs::write(&probe_path, b"probe")
+            .and_then(|_| fs::remove_file(&probe_path))
+            .map_err(|e| {
+                TezedgeNodeControllerError::TargetNotWritable(
+                    self.placement_directory.to_string_lossy().to_string(),
+                    e,
+                )
+            })
+    }
+
+    /// Writes the checksum sidecar of an archive and records the digest in its manifest
+    fn checksum(&self, snapshot_path: &Path, algorithm: &ChecksumAlgorithm) {
+        // a directory snapshot has no single file to hash
+        if snapshot_path.is_dir() {
+            return;
+        }
+
+        info!(self.log, "Computing the {} checksum of {}", algorithm, snapshot_path.to_string_lossy());
+        let result = checksum::write_checksum(snapshot_path, algorithm).and_then(|digest| {
+            let mut manifest = SnapshotManifest::read(snapshot_path)?;
+            manifest.checksum_algorithm = Some(algorithm.to_string());
+            manifest.checksum = Some(digest);
+            manifest.write(snapshot_path)
+        });
+        if let Err(e) = result {
+            warn!(self.log, "Failed to checksum {}: {}", snapshot_path.to_string_lossy(), e);
+        }
+    }
+
+    /// Writes the .torrent sidecar of the snapshot and records its magnet link in the manifest
+    fn write_torrent(&self, snapshot_path: &Path) {
+        // the web seeds serve the snapshot under its original name, the parts of a split archive do not match it
+        if !snapshot_path.exists() {
+            warn!(self.log, "No torrent is written for the split archive {}", snapshot_path.to_string_lossy());
+            return;
+        }
+
+        // the snapshot is served from <web seed>/<context>/<snapshot type>/, as laid out in the target directory
+        let type_directory = snapshot_path
+            .parent()
+            .and_then(|type_directory| Some((type_directory.parent()?.file_name()?, type_directory.file_name()?)));
+        let web_seeds: Vec<String> = match type_directory {
+            Some((context, kind)) => self
+                .torrent_web_seeds
+                .iter()
+                .map(|web_seed| {
+                    format!(
+                        "{}/{}/{}/",
+                        web_seed.as_str().trim_end_matches('/'),
+                        context.to_string_lossy(),
+                        kind.to_string_lossy()
+                    )
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        info!(self.log, "Writing the torrent of {}", snapshot_path.to_string_lossy());
+        let result = torrent::write_torrent(snapshot_path, &self.torrent_trackers, &web_seeds).and_then(|magnet| {
+            let mut manifest = SnapshotManifest::read(snapshot_path)?;
+            manifest.magnet = Some(magnet);
+            manifest.write(snapshot_path)
+        });
+        if let Err(e) = result {
+            warn!(self.log, "Failed to write the torrent of {}: {}", snapshot_path.to_string_lossy(), e);
+        }
+    }
+
+    /// Boots a throwaway node from the snapshot and marks it validated in its manifest once the node serves a head
+    async fn validate(&mut self, snapshot_path: &Path) {
+        self.set_stage(SnapshotStage::ValidatingSnapshot);
+        info!(self.log, "Validating snapshot {}", snapshot_path.to_string_lossy());
+
+        let validated = match self.boot_snapshot(snapshot_path).await {
+            Ok(()) => {
+                info!(self.log, "Snapshot {} validated", snapshot_path.to_string_lossy());
+                true
+            }
+            Err(e) => {
+                error!(self.log, "Snapshot {} failed validation: {}", snapshot_path.to_string_lossy(), e);
+                false
+            }
+        };
+
+        let result = SnapshotManifest::read(snapshot_path).and_then(|mut manifest| {
+            manifest.validated = validated;
+            manifest.write(snapshot_path)
+        });
+        if let Err(e) = result {
+            warn!(self.log, "Failed to update the manifest of {}: {}", snapshot_path.to_string_lossy(), e);
+        }
+    }
+
+    async fn boot_snapshot(&self, snapshot_path: &Path) -> Result<(), TezedgeNodeControllerError> {
+        let docker = self.docker()?;
+
+        let snapshot_name = snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        // extract under the placement directory, so the bind mount of the full snapshots can be reused
+        let data_path = self
+            .placement_directory
+            .join(manifest::temp_name(&format!("{}.validate", snapshot_name), &self.temp_token));
+        let cont_name = format!("tezedge-snapshots-validate-{}-{}", self.context_type, self.network);
+
+        if let Err(e) = unpack_snapshot(snapshot_path, &data_path) {
+            if data_path.exists() {
+                fs_extra::remove_items(&[data_path])?;
+            }
+            return Err(e.into());
+        }
+
+        let data_path_string = data_path.to_string_lossy().to_string();
+        let rpc_port = format!("--rpc-port={}", self.validation_rpc_port);
+        let p2p_port = format!("--p2p-port={}", self.validation_rpc_port.wrapping_add(1));
+        let with_context_type = self.context_type.to_string();
+        let entrypoint = vec![
+            "/light-node",
+            "--config-file=/tezedge.config",
+            &p2p_port,
+            &rpc_port,
+            "--init-sapling-spend-params-file=/sapling-spend.params",
+            "--init-sapling-output-params-file=/sapling-output.params",
+            "--network",
+            &self.network,
+            "--bootstrap-db-path=bootstrap_db",
+            "--tezos-data-dir",
+            &data_path_string,
+            "--tezos-context-storage",
+            &with_context_type,
+        ];
+
+        let snapshot_host_path = self.snapshot_host_path();
+        let host_config = HostConfig {
+            mounts: Some(vec![Mount {
+                target: Some(
+                    self.placement_directory
+                        .to_string_lossy()
+                        .to_string(),
+                ),
+                source: Some(snapshot_host_path),
+                typ: Some(MountTypeEnum::BIND),
+                ..Default::default()
+            }]),
+            network_mode: Some(String::from("host")),
+            ..Default::default()
+        };
+        let config = Config {
+            image: Some(self.full_snapshot_image.as_str()),
+            host_config: Some(host_config),
+            entrypoint: Some(entrypoint),
+            ..Default::default()
+        };
+
+        docker
+            .create_container::<String, &str>(Some(CreateContainerOptions { name: cont_name.clone() }), config)
+            .await?;
+
+        let result = self.wait_for_validation_head(&docker, &cont_name).await;
+
+        // the throwaway node and its data are removed whatever the outcome
+        docker
+            .remove_container(
+                &cont_name,
+                Some(RemoveContainerOptions {
+                    force: true,
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        fs_extra::remove_items(&[data_path])?;
+
+        result
+    }
+
+    async fn wait_for_validation_head(&self, docker: &Docker, cont_name: &str) -> Result<(), TezedgeNodeControllerError> {
+        docker.start_container::<String>(cont_name, None).await?;
+
+        let header_url = Url::parse(&format!(
+            "http://localhost:{}/chains/main/blocks/head/header",
+            self.validation_rpc_port
+        ))?;
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(self.validation_timeout) {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            if let Ok(response) = reqwest::get(header_url.clone()).await {
+                if response.json::<TezosBlockHeader>().await.is_ok() {
+                    return Ok(());
+                }
+            }
+            if !self.is_running(cont_name).await? {
+                return Err(TezedgeNodeControllerError::ValidationFailed(String::from("the node exited")));
+            }
+        }
+        Err(TezedgeNodeControllerError::ValidationFailed(String::from("timed out waiting for a head")))
+    }
+
+    /// Waits for the restarted node to serve its head again and reports the recovery, the snapshot itself is not affected
+    /// Waits for the restarted node to serve its head and returns the first one served
+    async fn wait_for_recovery(&mut self, stopped_at: Instant) -> Option<TezosBlockHeader> {
+        self.set_stage(SnapshotStage::WaitingForRecovery);
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(self.recovery_timeout) {
+            if let Ok(head) = self.get_head().await {
+                let message = format!(
+                    "Node recovered and serves its head {}s after it was stopped",
+                    stopped_at.elapsed().as_secs()
+                );
+                info!(self.log, "{}", message);
+                self.webhook.notify("node_recovered", &message).await;
+                return Some(head);
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+
+        let message = format!(
+            "Node did not serve its head within {}s after the restart",
+            self.recovery_timeout
+        );
+        crit!(self.log, "{}", message);
+        self.webhook.notify("node_not_recovered", &message).await;
+        None
+    }
+
+    /// Compares the head recorded in the manifests of the sub-snapshots with the first head the restarted node serves,
+    /// an independent reading of the database they were taken from. The node only moved on from the recorded head
+    /// until it was stopped, so the recovered head can not be below it, nor another block at its level
+    fn check_recorded_head(
+        &self,
+        snapshots: &[(String, PathBuf, u64)],
+        head_block_hash: &str,
+        head_level: i32,
+        recovered_head: &TezosBlockHeader,
+    ) {
+        let names = snapshots
+            .iter()
+            .map(|(_, snapshot_path, _)| snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        if is_recorded_head_consistent(head_block_hash, head_level, recovered_head) {
+            info!(
+                self.log,
+                "The snapshots {} record the same head, the restarted node resumed from level {}",
+                names,
+                recovered_head.level;
+                "block_hash" => head_block_hash,
+                "level" => head_level,
+            );
+        } else {
+            error!(
+                self.log,
+                "The snapshots {} record the head {} at level {}, the restarted node resumed from {} at level {}",
+                names,
+                head_block_hash,
+                head_level,
+                recovered_head.hash,
+                recovered_head.level
+            );
+        }
+    }
+
+    /// Writes last-error.json into the snapshot target directory, so tooling can classify fatal failures
+    pub fn write_last_error(&self, error: &TezedgeNodeControllerError) -> Result<(), std::io::Error> {
+        let mut error_chain = vec![error.to_string()];
+        let mut source = std::error::Error::source(error);
+        while let Some(e) = source {
+            error_chain.push(e.to_string());
+            source = e.source();
+        }
+
+        let last_error = LastError {
+            error_chain,
+            stage: self.stage,
+            timestamp: Utc::now().to_rfc3339(),
+        };
+        fs::write(
+            self.snapshots_target_directory.join("last-error.json"),
+            serde_json::to_vec_pretty(&last_error)?,
+        )
+    }
+
+    pub async fn can_snapshot(&mut self, snapshot_frequency: u64) -> bool {
+        // the node may still be replaying or bootstrapping right after a restart
+        if let Some(instant) = self.last_restart_timestamp {
+            if instant.elapsed() < Duration::from_secs(self.post_restart_grace) {
+                return false;
+            }
+        }
+
+        // a floor independent of the frequency, guarding against snapshot storms
+        if let Some(instant) = self.last_snapshot_timestamp {
+            if instant.elapsed() < Duration::from_secs(self.min_interval) {
+                return false;
+            }
+        }
+
+        if self.is_node_reachable().await {
+            if let Some(instant) = self.last_snapshot_timestamp {
+                instant.elapsed() >= Duration::from_secs(snapshot_frequency)
+            } else {
+                true
+            }
+        } else {
+            // if the node does not respond to the rpc, do not snapshot
+            // this catches a corner-case where, the node is started with a cleaned up DB
+            // and is not yet ready for the first snapshot
+            false
+        }
+    }
+
+    /// Polls the node's head at most once per rpc_poll_interval, reusing the last result in between
+    async fn is_node_reachable(&mut self) -> bool {
+        if let Some((instant, reachable)) = self.last_rpc_poll {
+            if instant.elapsed() < Duration::from_secs(self.rpc_poll_interval) {
+                return reachable;
+            }
+        }
+
+        let reachable = self.get_head().await.is_ok();
+        self.last_rpc_poll = Some((Instant::now(), reachable));
+        if let Ok(mut health) = self.health.write() {
+            health.node_reachable = reachable;
+        }
+        if reachable {
+            if self.unreachable_warnings > 0 {
+                info!(self.log, "The node is reachable again");
+            }
+            self.unreachable_since = None;
+            self.outage_since = None;
+            self.unreachable_warnings = 0;
+        } else {
+            self.unreachable_since.get_or_insert_with(Instant::now);
+            self.outage_since.get_or_insert_with(Instant::now);
+        }
+        self.check_unreachable_grace();
+        reachable
+    }
+
+    /// Escalates a node unreachable past the grace, the snapshots are otherwise just silently not taken.
+    /// The warnings come after the grace, twice the grace, four times the grace and so on
+    fn check_unreachable_grace(&mut self) {
+        let unreachable_secs = self.outage_since.map(|since| since.elapsed().as_secs()).unwrap_or_default();
+        self.metrics.set_node_unreachable_seconds(unreachable_secs);
+
+        if self.unreachable_grace == 0 {
+            return;
+        }
+        let threshold = self.unreachable_grace.saturating_mul(1 << self.unreachable_warnings.min(32));
+        if unreachable_secs < threshold {
+            return;
+        }
+
+        self.unreachable_warnings += 1;
+        let message = format!("The node has been unreachable for {}s, no snapshots are taken", unreachable_secs);
+        match self.unreachable_warnings {
+            1 => warn!(self.log, "{}", message),
+            2 => error!(self.log, "{}", message),
+            _ => crit!(self.log, "{}", message),
+        }
+    }
+
+    /// Restarts the node container once it stayed unreachable for the watchdog timeout, returns whether it was restarted
+    pub async fn check_watchdog(&mut self) -> Result<bool, TezedgeNodeControllerError> {
+        let (timeout, unreachable_since) = match (self.watchdog_timeout, self.unreachable_since) {
+            (Some(timeout), Some(unreachable_since)) => (timeout, unreachable_since),
+            _ => return Ok(false),
+        };
+        if unreachable_since.elapsed() < Duration::from_secs(timeout) {
+            return Ok(false);
+        }
+
+        crit!(
+            self.log,
+            "Node unreachable for {}s, restarting container {}",
+            unreachable_since.elapsed().as_secs(),
+            self.node_container_name
+        );
+        // the next restart only after another full timeout
+        self.unreachable_since = None;
+        let docker = self.docker()?;
+        self.with_docker_retry("restart node", || docker.restart_container(&self.node_container_name, None))
+            .await?;
+        self.last_restart_timestamp = Some(Instant::now());
+        Ok(true)
+    }
+
+    /// The compression of the snapshot type, archive or full, the global compression unless set per type
+    fn compression_of(&self, kind: &str) -> CompressionSetting {
+        let compression = match kind {
+            "archive" => &self.archive_compression,
+            _ => &self.full_compression,
+        };
+        compression.clone().unwrap_or(CompressionSetting {
+            compression: self.compression.clone(),
+            level: None,
+        })
+    }
+
+    /// Extension of the archives written with the compression, the external compressor overrides the built in ones
+    fn archive_extension<'a>(&'a self, compression: &CompressionSetting) -> Option<&'a str> {
+        match &self.external_compressor {
+            Some(_) => Some(&self.external_compressor_extension),
+            None => compression.compression.extension(),
+        }
+    }
+
+    /// Appends the compression specific extension to tarball snapshot names, if any
+    fn final_snapshot_name(&self, snapshot_name: String, compression: &CompressionSetting) -> String {
+        match (&self.format, self.archive_extension(compression)) {
+            (SnapshotFormat::Tar, Some(extension)) => format!("{}.{}", snapshot_name, extension),
+            _ => snapshot_name,
+        }
+    }
+
+    /// Copies the tezedge database directories into an uncompressed snapshot directory
+    fn create_tezedge_dir_copy(&self, dir_name: &str, source: &Path, destination: &Path) -> Result<(), TezedgeNodeControllerError> {
+        let target = destination.join(dir_name);
+        dir::create_all(&target, false)?;
+
+        for member in self.node_layout.members() {
+            info!(self.log, "Copying to snapshot directory: {}", source.join(member).to_string_lossy());
+            copy_dir_reflink(&source.join(member), &target.join(member))?;
+        }
+        self.metrics.add_bytes_written(snapshot_size(&target)?);
+        Ok(())
+    }
+
+    /// Name of the archive of a single database directory in a members snapshot
+    fn member_archive_name(&self, member: &str, compression: &CompressionSetting) -> String {
+        format!("{}.{}", member, self.archive_extension(compression).unwrap_or("tar.gz"))
+    }
+
+    /// Creates a snapshot directory with a separate archive per database directory, so each can be restored on its own
+    fn create_tezedge_member_archives(
+        &self,
+        dir_name: &str,
+        source: &Path,
+        destination: &Path,
+        compression: &CompressionSetting,
+    ) -> Result<u64, TezedgeNodeControllerError> {
+        let target = destination.join(dir_name);
+        dir::create_all(&target, false)?;
+
+        let mut uncompressed_size = 0;
+        for member in self.node_layout.members() {
+            uncompressed_size += self.create_tezedge_tar_archive(
+                &self.member_archive_name(member, compression),
+                source,
+                &target,
+                &[member],
+                compression,
+            )?;
+        }
+        Ok(uncompressed_size)
+    }
+
+    /// Creates the archive of the members and returns the size in bytes of the uncompressed tar stream
+    fn create_tezedge_tar_archive(
+        &self,
+        archive_name: &str,
+        source: &Path,
+        destination: &Path,
+        members: &[&str],
+        compression: &CompressionSetting,
+    ) -> Result<u64, std::io::Error> {
+        // assemble the archive in the scratch directory when configured, the destination may be a slow mount
+        let build_directory = self.scratch_directory.as_deref().unwrap_or(destination);
+        // buffer the many small tar writes instead of hitting the file with a syscall each
+        let tar_file = BufWriter::with_capacity(
+            self.compression_buffer_size,
+            std::fs::File::create(build_directory.join(archive_name))?,
+        );
+        let (tar_file, uncompressed_size) = self.compress_tezedge_dirs(tar_file, source, members, compression)?;
+        let tar_file = tar_file.into_inner().map_err(|e| e.into_error())?;
+        self.metrics.add_bytes_written(tar_file.metadata()?.len());
+
+        if build_directory != destination {
+            info!(self.log, "Moving the archive from the scratch directory to {}", destination.to_string_lossy());
+            move_file(&build_directory.join(archive_name), &destination.join(archive_name))?;
+        }
+        Ok(uncompressed_size)
+    }
+
+    /// Writes the compressed tar stream of the tezedge database directories and returns the writer with the tar stream size
+    fn compress_tezedge_dirs<W: Write + Send>(
+        &self,
+        writer: W,
+        source: &Path,
+        members: &[&str],
+        compression: &CompressionSetting,
+    ) -> Result<(W, u64), std::io::Error> {
+        let nice = match self.compression_nice {
+            Some(nice) => nice,
+            None => return self.compress_tezedge_dirs_on_current_thread(writer, source, members, compression),
+        };
+        // a thread of its own exiting afterwards, a reniced runtime worker would slow down whatever task it runs next
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    // the xz worker threads and the external compressor are spawned from this thread and inherit its niceness
+                    set_thread_niceness(nice, &self.log);
+                    self.compress_tezedge_dirs_on_current_thread(writer, source, members, compression)
+                })
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("The compression thread panicked")))
+        })
+    }
+
+    fn compress_tezedge_dirs_on_current_thread<W: Write + Send>(
+        &self,
+        writer: W,
+        source: &Path,
+        members: &[&str],
+        compression: &CompressionSetting,
+    ) -> Result<(W, u64), std::io::Error> {
+        if let Some(command) = &self.external_compressor {
+            return self.compress_externally(writer, source, members, command);
+        }
+        let compression = match self.compression_time_budget {
+            Some(budget) => self.budgeted_compression(source, members, compression, budget)?,
+            None => compression.clone(),
+        };
+        match compression.compression {
+            CompressionType::Gzip => {
+                // the fastest level by default, the node is stopped while the archive is written
+                let level = compression.level.map(Compression::new).unwrap_or_else(Compression::fast);
+                let enc = GzEncoder::new(writer, level);
+                let (enc, uncompressed_size) = self.append_tezedge_dirs(enc, source, members)?;
+                Ok((enc.finish()?, uncompressed_size))
+            }
+            CompressionType::Xz => {
+                // preset 6 is the xz default, favouring size over speed for cold archival
+                let stream = MtStreamBuilder::new()
+                    .threads(self.compression_threads)
+                    .preset(compression.level.unwrap_or(6))
+                    .check(Check::Crc64)
+                    .encoder()?;
+                let enc = XzEncoder::new_stream(writer, stream);
+                let (enc, uncompressed_size) = self.append_tezedge_dirs(enc, source, members)?;
+                Ok((enc.finish()?, uncompressed_size))
+            }
+        }
+    }
+
+    /// Pipes the uncompressed tar stream through the external compressor, its output is copied to the writer from a
+    /// separate thread so neither side of the compressor blocks on a full pipe
+    fn compress_externally<W: Write + Send>(
+        &self,
+        mut writer: W,
+        source: &Path,
+        members: &[&str],
+        command: &str,
+    ) -> Result<(W, u64), std::io::Error> {
+        info!(self.log, "Compressing with the external compressor: {}", command);
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("The compressor stdin is piped");
+        let mut stdout = child.stdout.take().expect("The compressor stdout is piped");
+        // killed by whichever side fails first, a compressor blocked on a full pipe would never exit on its own
+        let child = Mutex::new(child);
+        let kill = || {
+            if let Ok(mut child) = child.lock() {
+                let _ = child.kill();
+            }
+        };
+
+        let writer_ref = &mut writer;
+        let (appended, copied) = std::thread::scope(|scope| {
+            // stdout is moved in, a failed copy drops it and the compressor gets a broken pipe
+            let copier = scope.spawn(move || {
+                let copied = std::io::copy(&mut stdout, writer_ref);
+                drop(stdout);
+                if copied.is_err() {
+                    kill();
+                }
+                copied
+            });
+            // stdin is dropped at the end of the closure, closing it lets the compressor finish
+            let appended = self
+                .append_tezedge_dirs(BufWriter::with_capacity(self.compression_buffer_size, stdin), source, members)
+                .and_then(|(stdin, uncompressed_size)| {
+                    stdin.into_inner().map_err(|e| e.into_error())?;
+                    Ok(uncompressed_size)
+                });
+            if appended.is_err() {
+                kill();
+            }
+            let copied = copier
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("The compressor output copy panicked")));
+            (appended, copied)
+        });
+
+        let mut child = child.into_inner().map_err(|_| std::io::Error::other("The compressor lock is poisoned"))?;
+        let status = child.wait()?;
+        let uncompressed_size = appended?;
+        copied?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!("The external compressor exited with {}", status)));
+        }
+        Ok((writer, uncompressed_size))
+    }
+
+    /// Measures the throughput of the levels the budget may pick from on a sample from the start of the members,
+    /// run before the node is stopped so the sampling does not add to its downtime
+    fn calibrate_compression(&mut self, kinds: &[&str]) {
+        self.compression_throughput.clear();
+        for kind in kinds {
+            let compression = self.compression_of(kind);
+            // the levels below the highest one sampled are the same for every setting
+            let highest = compression.level.unwrap_or(9);
+            if self
+                .compression_throughput
+                .iter()
+                .any(|(setting, _)| setting.compression == compression.compression && setting.level == Some(highest))
+            {
+                continue;
+            }
+            match self.sample_throughput(&self.database_directory, self.node_layout.members(), &compression) {
+                Ok(throughput) => self.compression_throughput.extend(throughput),
+                Err(e) => warn!(self.log, "Compression calibration failed, using the configured {} level: {}", compression.compression, e),
+            }
+        }
+    }
+
+    /// Bytes per second of each level from the highest, the configured one at most, down to the fastest
+    fn sample_throughput(
+        &self,
+        source: &Path,
+        members: &[&str],
+        compression: &CompressionSetting,
+    ) -> Result<Vec<(CompressionSetting, f64)>, std::io::Error> {
+        let mut files = vec![];
+        for member in members {
+            let content = dir::get_dir_content(source.join(member))
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            files.extend(content.files.into_iter().map(PathBuf::from));
+        }
+
+        let fastest = match compression.compression {
+            CompressionType::Gzip => 1,
+            CompressionType::Xz => 0,
+        };
+        let highest = compression.level.unwrap_or(9);
+        let mut levels: Vec<u32> = vec![highest, 6, 3, fastest].into_iter().filter(|level| *level <= highest).collect();
+        levels.dedup();
+
+        let mut throughput = vec![];
+        for level in levels {
+            let setting = CompressionSetting {
+                compression: compression.compression.clone(),
+                level: Some(level),
+            };
+            let started = Instant::now();
+            let sampled = self.compress_sample(&files, &setting)?;
+            let elapsed = started.elapsed().as_secs_f64();
+            // an empty or instantly compressed sample fits any budget
+            let bytes_per_second = if sampled == 0 || elapsed == 0.0 { f64::INFINITY } else { sampled as f64 / elapsed };
+            throughput.push((setting, bytes_per_second));
+        }
+        Ok(throughput)
+    }
+
+    /// Picks the highest calibrated level, the configured one at most, estimated to compress the members within the budget.
+    /// The fastest calibrated level is used when none fits, the configured one when the calibration failed
+    fn budgeted_compression(
+        &self,
+        source: &Path,
+        members: &[&str],
+        compression: &CompressionSetting,
+        budget: u64,
+    ) -> Result<CompressionSetting, std::io::Error> {
+        let highest = compression.level.unwrap_or(9);
+        let mut calibrated: Vec<&(CompressionSetting, f64)> = self
+            .compression_throughput
+            .iter()
+            .filter(|(setting, _)| setting.compression == compression.compression && setting.level.unwrap_or(9) <= highest)
+            .collect();
+        calibrated.sort_by_key(|(setting, _)| std::cmp::Reverse(setting.level));
+        calibrated.dedup_by(|(a, _), (b, _)| a.level == b.level);
+        let fastest = match calibrated.last() {
+            Some((setting, _)) => setting.clone(),
+            None => return Ok(compression.clone()),
+        };
+
+        let total_size = members
+            .iter()
+            .map(|member| dir::get_size(source.join(member)))
+            .sum::<Result<u64, _>>()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        for (setting, bytes_per_second) in calibrated {
+            let estimate = total_size as f64 / bytes_per_second;
+            info!(
+                self.log,
+                "{} level {} is estimated to take {:.0}s of the {}s compression time budget",
+                setting.compression,
+                setting.level.unwrap_or(9),
+                estimate,
+                budget
+            );
+            if estimate <= budget as f64 {
+                return Ok(setting.clone());
+            }
+        }
+
+        warn!(self.log, "Even the fastest {} level is estimated to exceed the compression time budget", compression.compression);
+        Ok(fastest)
+    }
+
+    /// Compresses the start of the files into nothing, returns the number of bytes sampled
+    fn compress_sample(&self, files: &[PathBuf], compression: &CompressionSetting) -> Result<u64, std::io::Error> {
+        let sample = |enc: &mut dyn Write| -> Result<u64, std::io::Error> {
+            let mut sampled = 0;
+            for file in files {
+                if sampled >= COMPRESSION_SAMPLE_SIZE {
+                    break;
+                }
+                sampled += std::io::copy(&mut fs::File::open(file)?.take(COMPRESSION_SAMPLE_SIZE - sampled), enc)?;
+            }
+            Ok(sampled)
+        };
+        let level = compression.level.unwrap_or(6);
+        match compression.compression {
+            CompressionType::Gzip => {
+                let mut enc = GzEncoder::new(std::io::sink(), Compression::new(level));
+                let sampled = sample(&mut enc)?;
+                enc.finish()?;
+                Ok(sampled)
+            }
+            CompressionType::Xz => {
+                let stream = MtStreamBuilder::new()
+                    .threads(self.compression_threads)
+                    .preset(level)
+                    .check(Check::Crc64)
+                    .encoder()?;
+                let mut enc = XzEncoder::new_stream(std::io::sink(), stream);
+                let sampled = sample(&mut enc)?;
+                enc.finish()?;
+                Ok(sampled)
+            }
+        }
+    }
+
+    /// Appends a directory tree entry by entry, logging each file at trace level
+    fn append_tree<W: Write>(&self, tar: &mut tar::Builder<W>, name: &Path, path: &Path) -> Result<(), std::io::Error> {
+        let excluded = self.is_excluded(name);
+        if !path.is_dir() {
+            if excluded {
+                return Ok(());
+            }
+            trace!(self.log, "Adding {} ({} bytes)", name.to_string_lossy(), fs::metadata(path)?.len());
+            return tar.append_path_with_name(path, name);
+        }
+
+        // an excluded directory may still hold included entries, only its own entry is left out
+        if !excluded {
+            tar.append_dir(name, path)?;
+        }
+        // sorted for reproducible archives, read_dir has no defined order
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            self.append_tree(tar, &name.join(&entry), &path.join(&entry))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the archive entry is left out by the context include globs, matched against its path below the context
+    /// directory. A matching directory includes everything below it, the parents of included entries are created on
+    /// unpacking
+    fn is_excluded(&self, name: &Path) -> bool {
+        if self.context_include.is_empty() {
+            return false;
+        }
+        let relative = match name.strip_prefix("context") {
+            Ok(relative) if !relative.as_os_str().is_empty() => relative,
+            _ => return false,
+        };
+        !relative.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()).any(|ancestor| {
+            let ancestor = ancestor.to_string_lossy();
+            self.context_include.iter().any(|pattern| glob::matches(pattern, &ancestor))
+        })
+    }
+
+    /// Writes the tezedge database directories into a tar stream and returns the underlying writer with the tar stream size
+    fn append_tezedge_dirs<W: Write>(&self, writer: W, source: &Path, members: &[&str]) -> Result<(W, u64), std::io::Error> {
+        let estimated_total = members
+            .iter()
+            .map(|member| dir::get_size(source.join(member)).unwrap_or(0))
+            .sum();
+        let mut tar = tar::Builder::new(CountingWriter::new(writer, self.status.clone(), estimated_total));
+        for member in members {
+            crit!(self.log, "Adding to archive: {}", source.join(member).to_string_lossy());
+            self.append_tree(&mut tar, Path::new(member), &source.join(member))?;
+        }
+        let counting_writer = tar.into_inner()?;
+        counting_writer.report();
+        Ok((counting_writer.inner, counting_writer.count))
+    }
+}
+
+/// Renices the calling thread, only meant for a thread exiting after the work it is reniced for
+fn set_thread_niceness(nice: i32, log: &Logger) {
+    #[cfg(target_os = "linux")]
+    {
+        // on Linux the niceness is per thread, the process niceness would also slow down the API and the runtime
+        let tid = unsafe { libc::gettid() } as libc::id_t;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+            warn!(log, "Failed to set the compression niceness to {}: {}", nice, std::io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    warn!(log, "The compression niceness {} is ignored, it is only supported on Linux", nice);
+}
+
+/// Bytes written between two progress updates of the status
+const PROGRESS_REPORT_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Counts the bytes written through it, reporting the progress against the estimated total in the status
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+    status: SharedStatus,
+    estimated_total: u64,
+    reported: u64,
+    started: Instant,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W, status: SharedStatus, estimated_total: u64) -> Self {
+        let writer = Self {
+            inner,
+            count: 0,
+            status,
+            estimated_total,
+            reported: 0,
+            started: Instant::now(),
+        };
+        writer.report();
+        writer
+    }
+
+    fn report(&self) {
+        let fraction = if self.estimated_total > 0 {
+            // the tar headers make the stream slightly larger than the estimate
+            (self.count as f64 / self.estimated_total as f64).min(1.0)
+        } else {
+            0.0
+        };
+        let remaining_secs = if fraction > 0.0 {
+            Some((self.started.elapsed().as_secs_f64() * (1.0 - fraction) / fraction) as u64)
+        } else {
+            None
+        };
+        if let Ok(mut status) = self.status.write() {
+            status.bytes_written = Some(self.count);
+            status.estimated_total_bytes = Some(self.estimated_total);
+            status.percent_complete = Some(fraction * 100.0);
+            status.estimated_remaining_secs = remaining_secs;
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        if self.count - self.reported >= PROGRESS_REPORT_INTERVAL {
+            self.reported = self.count;
+            self.report();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Recursively copies a directory, using copy-on-write reflinks on filesystems supporting them (XFS, btrfs)
+/// and falling back to a regular copy elsewhere
+fn copy_dir_reflink(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    fs::create_dir_all(destination)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_reflink(&entry.path(), &target)?;
+        } else {
+            reflink::reflink_or_copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves a file, falling back to copy and remove when the rename crosses filesystems
+fn move_file(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    if fs::rename(source, destination).is_err() {
+        fs::copy(source, destination)?;
+        fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+/// Copies the file into consecutive parts of at most split_size bytes next to it
+fn split_file(path: &Path, split_size: u64) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut parts = vec![];
+    loop {
+        let part_path = manifest::part_path(path, parts.len());
+        let mut part = fs::File::create(&part_path)?;
+        let copied = std::io::copy(&mut (&mut file).take(split_size), &mut part)?;
+        if copied == 0 {
+            fs::remove_file(part_path)?;
+            break;
+        }
+        part.sync_all()?;
+        parts.push(part_path);
+    }
+    Ok(parts)
+}
+
+/// Flushes a file, or every file and directory of a directory snapshot, to the disk
+fn sync_path(path: &Path) -> Result<(), std::io::Error> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            sync_path(&entry?.path())?;
+        }
+    }
+    fs::File::open(path)?.sync_all()
+}
+
+fn chown_recursive(path: &Path, uid: u32, gid: u32) -> Result<(), std::io::Error> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            chown_recursive(&entry?.path(), uid, gid)?;
+        }
+    }
+    std::os::unix::fs::chown(path, Some(uid), Some(gid))
+}
+
+/// Whether this process runs inside a docker or podman container
+/// Whether the head recovered from the database can follow the recorded head, see check_recorded_head
+fn is_recorded_head_consistent(head_block_hash: &str, head_level: i32, recovered_head: &TezosBlockHeader) -> bool {
+    recovered_head.level > head_level || (recovered_head.level == head_level && recovered_head.hash == head_block_hash)
+}
+
+fn is_containerized() -> bool {
+    Path::new("/.dockerenv").exists() || Path::new("/run/.containerenv").exists()
+}
+
+/// The url without the credentials, for logging
+fn redacted_url(url: &Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url.to_string()
+}
+
+/// Whether the docker error is a transient failure of the daemon or the connection to it, not of the operation
+fn is_retryable_docker_error(error: &bollard::errors::Error) -> bool {
+    match error {
+        bollard::errors::Error::DockerResponseServerError { status_code, .. } => *status_code >= 500,
+        bollard::errors::Error::HyperResponseError { .. }
+        | bollard::errors::Error::IOError { .. }
+        | bollard::errors::Error::RequestTimeoutError => true,
+        _ => false,
+    }
+}
+
+/// Removes the transient files a node leaves in its bootstrap database, the RocksDB info logs and lock files,
+/// returns the number of bytes removed
+fn clean_bootstrap_db(path: &Path) -> Result<u64, std::io::Error> {
+    let mut removed = 0;
+    if !path.is_dir() {
+        return Ok(removed);
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if path.is_dir() {
+            removed += clean_bootstrap_db(&path)?;
+        } else if name == "LOG" || name == "LOCK" || name.starts_with("LOG.old.") {
+            removed += fs::metadata(&path)?.len();
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Size of an archive or of a directory snapshot in bytes
+fn snapshot_size(snapshot_path: &Path) -> Result<u64, fs_extra::error::Error> {
+    if snapshot_path.is_dir() {
+        dir::get_size(snapshot_path)
+    } else {
+        let mut size = 0;
+        for snapshot_file in manifest::snapshot_files(snapshot_path) {
+            size += fs::metadata(snapshot_file)?.len();
+        }
+        Ok(size)
+    }
+}
+
+/// Removes the snapshot together with its sidecars
+fn remove_snapshot(snapshot_path: &Path) -> Result<(), fs_extra::error::Error> {
+    let mut to_remove = manifest::sidecars(snapshot_path);
+    to_remove.extend(manifest::parts(snapshot_path));
+    if snapshot_path.exists() {
+        to_remove.push(snapshot_path.to_path_buf());
+    }
+    fs_extra::remove_items(&to_remove)
+}
+
+/// Unpacks a snapshot into the destination, detecting the snapshot format from its name
+fn unpack_snapshot(snapshot_path: &Path, destination: &Path) -> Result<(), std::io::Error> {
+    if snapshot_path.is_dir() {
+        // a members snapshot holds an archive per database directory, a dir snapshot the directories themselves
+        let member_archives = fs::read_dir(snapshot_path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?
+            .into_iter()
+            .filter(|p| p.is_file() && (p.to_string_lossy().ends_with(".tar.gz") || p.to_string_lossy().ends_with(".tar.xz")))
+            .collect::<Vec<PathBuf>>();
+        if member_archives.is_empty() {
+            return copy_dir_reflink(snapshot_path, destination);
+        }
+        for member_archive in member_archives {
+            unpack_snapshot(&member_archive, destination)?;
+        }
+        return Ok(());
+    }
+
+    let file = fs::File::open(snapshot_path)?;
+    let name = snapshot_path.to_string_lossy();
+    if name.ends_with(".tar.xz") {
+        tar::Archive::new(XzDecoder::new(file)).unpack(destination)
+    } else {
+        tar::Archive::new(GzDecoder::new(file)).unpack(destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    /// A controller of the database and target directories with the settings, see SnapshotSettings::default
+    fn controller(database_directory: &Path, target_directory: &Path, settings: SnapshotSettings) -> TezedgeNodeController {
+        let log = Logger::root(slog::Discard, slog::o!());
+        TezedgeNodeController::new(
+            Url::parse("http://localhost:18732").unwrap(),
+            String::from("tezedge-node"),
+            String::from("tezedge-node-monitoring"),
+            String::from("network"),
+            database_directory.to_path_buf(),
+            target_directory.to_path_buf(),
+            String::from("tezedge/tezedge:latest"),
+            ContextType::Irmin,
+            settings,
+            Webhook::new(None, log.clone()),
+            EventSink::new(None, String::from("tezedge.snapshots"), String::from("test"), log.clone()),
+            String::from("test"),
+            log,
+        )
+    }
+
+    /// A database directory with nested directories and files in both members
+    fn synthetic_database(directory: &Path) {
+        let files = vec![
+            ("context/index/store.pack", vec![0u8; 64 * 1024]),
+            ("context/index/store.dict", b"dictionary".to_vec()),
+            ("context/store.branches", (0..=255u8).cycle().take(100_000).collect()),
+            ("bootstrap_db/db/000001.log", b"rocksdb log".to_vec()),
+            ("bootstrap_db/db/CURRENT", b"MANIFEST-000001\n".to_vec()),
+            ("bootstrap_db/empty", vec![]),
+        ];
+        for (path, content) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    /// The relative paths and contents of all the files under the directory
+    fn tree(directory: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![directory.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in fs::read_dir(current).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else {
+                    files.insert(path.strip_prefix(directory).unwrap().to_path_buf(), fs::read(&path).unwrap());
+                }
+            }
+        }
+        files
+    }
+
+    /// Archives the synthetic database and returns the tree of the database and the tree unpacked from the archive
+    fn round_trip(
+        node: impl FnOnce(&Path, &Path) -> TezedgeNodeController,
+        compression: CompressionType,
+        decode: fn(fs::File) -> Box<dyn Read>,
+    ) -> (BTreeMap<PathBuf, Vec<u8>>, BTreeMap<PathBuf, Vec<u8>>) {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let extracted = tempfile::tempdir().unwrap();
+        synthetic_database(database.path());
+
+        let setting = CompressionSetting {
+            compression,
+            level: None,
+        };
+        let uncompressed_size = node(database.path(), target.path())
+            .create_tezedge_tar_archive("snapshot", database.path(), target.path(), &["context", "bootstrap_db"], &setting)
+            .unwrap();
+
+        let archive = fs::File::open(target.path().join("snapshot")).unwrap();
+        assert!(archive.metadata().unwrap().len() < uncompressed_size);
+        tar::Archive::new(decode(archive)).unpack(extracted.path()).unwrap();
+        (tree(database.path()), tree(extracted.path()))
+    }
+
+    fn assert_round_trip(compression: CompressionType, external_compressor: Option<&str>, decode: fn(fs::File) -> Box<dyn Read>) {
+        let (database, extracted) = round_trip(
+            |database, target| {
+                let settings = SnapshotSettings {
+                    external_compressor: external_compressor.map(|command| command.to_string()),
+                    ..Default::default()
+                };
+                controller(database, target, settings)
+            },
+            compression,
+            decode,
+        );
+        assert_eq!(extracted, database);
+    }
+
+    #[test]
+    fn tar_archive_round_trip_gzip() {
+        assert_round_trip(CompressionType::Gzip, None, |file| Box::new(GzDecoder::new(file)));
+    }
+
+    #[test]
+    fn tar_archive_round_trip_xz() {
+        assert_round_trip(CompressionType::Xz, None, |file| Box::new(XzDecoder::new(file)));
+    }
+
+    #[test]
+    fn tar_archive_round_trip_external_compressor() {
+        assert_round_trip(CompressionType::Gzip, Some("gzip -c"), |file| Box::new(GzDecoder::new(file)));
+    }
+
+    #[test]
+    fn tar_archive_context_include() {
+        let (mut database, extracted) = round_trip(
+            |database, target| {
+                let settings = SnapshotSettings {
+                    context_include: vec![String::from("index/*.pack"), String::from("store.branches")],
+                    ..Default::default()
+                };
+                controller(database, target, settings)
+            },
+            CompressionType::Gzip,
+            |file| Box::new(GzDecoder::new(file)),
+        );
+        database.remove(Path::new("context/index/store.dict"));
+        assert_eq!(extracted, database);
+    }
+
+    /// A destination failing like a full disk
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from_raw_os_error(libc::ENOSPC))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn external_compressor_failing_writer() {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        synthetic_database(database.path());
+        // well beyond the pipe buffers, the compressor blocks on its output long before it read all of it
+        fs::write(database.path().join("context/index/store.large"), vec![1u8; 8 * 1024 * 1024]).unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let database_directory = database.path().to_path_buf();
+        let target_directory = target.path().to_path_buf();
+        std::thread::spawn(move || {
+            let node = controller(&database_directory, &target_directory, SnapshotSettings::default());
+            let result = node.compress_externally(FailingWriter, &database_directory, &["context", "bootstrap_db"], "cat");
+            let _ = sender.send(result.map(|(_, uncompressed_size)| uncompressed_size));
+        });
+
+        let result = receiver
+            .recv_timeout(std::time::Duration::from_secs(60))
+            .expect("The external compression deadlocked on the failing writer");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn budgeted_compression_uses_the_calibration() {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        synthetic_database(database.path());
+        let settings = SnapshotSettings {
+            compression: CompressionType::Gzip,
+            compression_time_budget: Some(10),
+            ..Default::default()
+        };
+        let mut node = controller(database.path(), target.path(), settings);
+        let members = ["context", "bootstrap_db"];
+        let gzip = |level| CompressionSetting {
+            compression: CompressionType::Gzip,
+            level,
+        };
+
+        // nothing calibrated, the configured setting is kept
+        let chosen = node.budgeted_compression(database.path(), &members, &gzip(None), 10).unwrap();
+        assert_eq!(chosen.level, None);
+
+        node.calibrate_compression(&["archive", "full"]);
+        let levels: Vec<Option<u32>> = node.compression_throughput.iter().map(|(setting, _)| setting.level).collect();
+        assert_eq!(levels, vec![Some(9), Some(6), Some(3), Some(1)]);
+
+        let size = members.iter().map(|member| dir::get_size(database.path().join(member)).unwrap()).sum::<u64>() as f64;
+        // only levels 3 and below fit the budget
+        node.compression_throughput = vec![
+            (gzip(Some(9)), size / 40.0),
+            (gzip(Some(6)), size / 20.0),
+            (gzip(Some(3)), size / 5.0),
+            (gzip(Some(1)), size),
+        ];
+        let chosen = node.budgeted_compression(database.path(), &members, &gzip(None), 10).unwrap();
+        assert_eq!(chosen.level, Some(3));
+        // a configured level caps the choice
+        let chosen = node.budgeted_compression(database.path(), &members, &gzip(Some(1)), 10).unwrap();
+        assert_eq!(chosen.level, Some(1));
+        // none fits, the fastest is used
+        let chosen = node.budgeted_compression(database.path(), &members, &gzip(None), 0).unwrap();
+        assert_eq!(chosen.level, Some(1));
+    }
+
+    #[test]
+    fn recorded_head_consistency() {
+        let recovered = |hash: &str, level| TezosBlockHeader {
+            hash: hash.to_string(),
+            level,
+        };
+        assert!(is_recorded_head_consistent("BLa", 100, &recovered("BLa", 100)));
+        assert!(is_recorded_head_consistent("BLa", 100, &recovered("BLb", 101)));
+        assert!(!is_recorded_head_consistent("BLa", 100, &recovered("BLb", 100)));
+        assert!(!is_recorded_head_consistent("BLa", 100, &recovered("BLc", 99)));
+    }
+
+    #[tokio::test]
+    async fn remove_lock_file_missing() {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        synthetic_database(database.path());
+        assert!(!database.path().join("context/index/lock").exists());
+
+        let node = controller(database.path(), target.path(), SnapshotSettings::default());
+        assert!(node.remove_lock_file().await.is_ok());
+    }
+
+    #[test]
+    fn full_snapshot_cleanup_after_failed_step() {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let snapshot_path = target.path().join("snapshot.full-dir.temp");
+        let snapshot_path_temp = target.path().join("snapshot.full.temp");
+        synthetic_database(&snapshot_path);
+        fs::write(&snapshot_path_temp, b"partial archive").unwrap();
+
+        let node = controller(database.path(), target.path(), SnapshotSettings::default());
+        // the failed step is reported, not the failed container removal
+        let result = node.clean_up_full_snapshot(
+            Err(TezedgeNodeControllerError::NodeUnreachable),
+            Err(TezedgeNodeControllerError::ContainerNotFound(String::from("tezedge-snapshots-full"))),
+            &snapshot_path,
+            &snapshot_path_temp,
+        );
+        assert!(matches!(result, Err(TezedgeNodeControllerError::NodeUnreachable)));
+        assert!(!snapshot_path.exists());
+        assert!(!snapshot_path_temp.exists());
+    }
+
+    #[test]
+    fn snapshot_time_sets_mtime() {
+        let database = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let snapshot_path = target.path().join("snapshot.tar.gz");
+        fs::write(&snapshot_path, b"archive").unwrap();
+
+        let node = controller(database.path(), target.path(), SnapshotSettings::default());
+        node.set_snapshot_time(&snapshot_path, "2021-11-08T10:41:56.250Z");
+
+        let modified = fs::metadata(&snapshot_path).unwrap().modified().unwrap();
+        let expected = std::time::UNIX_EPOCH + Duration::from_millis(1_636_368_116_250);
+        assert_eq!(modified, expected);
+    }
+}
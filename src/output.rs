@@ -0,0 +1,163 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::configuration::OutputFormat;
+
+/// Result of a subcommand, printed for humans or as JSON for scripts
+pub trait CommandOutput: Serialize {
+    /// The human readable rendering
+    fn text(&self) -> String;
+}
+
+/// Prints the result of a subcommand to stdout, the logs go to stderr
+pub fn print<T: CommandOutput>(output: &T, format: &OutputFormat) {
+    match format {
+        OutputFormat::Text => println!("{}", output.text()),
+        OutputFormat::Json => match serde_json::to_string_pretty(output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize the output: {}", e),
+        },
+    }
+}
+
+/// Version of a component, or why it could not be determined
+#[derive(Debug, Serialize)]
+pub struct ComponentVersion {
+    pub version: Option<String>,
+    pub error: Option<String>,
+}
+
+impl<E: Display> From<Result<String, E>> for ComponentVersion {
+    fn from(result: Result<String, E>) -> Self {
+        match result {
+            Ok(version) => Self {
+                version: Some(version),
+                error: None,
+            },
+            Err(e) => Self {
+                version: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+impl ComponentVersion {
+    fn text(&self, name: &str) -> String {
+        match (&self.version, &self.error) {
+            (Some(version), _) => format!("{} {}", name, version),
+            (None, error) => format!("{} unavailable: {}", name, error.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionOutput {
+    pub tool: String,
+    pub node: ComponentVersion,
+    pub docker: ComponentVersion,
+    pub full_snapshot_image: ComponentVersion,
+}
+
+impl CommandOutput for VersionOutput {
+    fn text(&self) -> String {
+        [
+            format!("tezedge-snapshots {}", self.tool),
+            self.node.text("node"),
+            self.docker.text("docker"),
+            self.full_snapshot_image.text("full snapshot image"),
+        ]
+        .join("\n")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberSize {
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EstimateOutput {
+    pub members: Vec<MemberSize>,
+    pub uncompressed_size_bytes: u64,
+    pub compression: String,
+    pub estimated_compressed_size_bytes: u64,
+}
+
+impl CommandOutput for EstimateOutput {
+    fn text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .members
+            .iter()
+            .map(|member| format!("{}: {} bytes", member.name, member.size_bytes))
+            .collect();
+        lines.push(format!("Estimated uncompressed size: {} bytes", self.uncompressed_size_bytes));
+        lines.push(format!(
+            "Estimated compressed size ({}): ~{} bytes",
+            self.compression, self.estimated_compressed_size_bytes
+        ));
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneOutput {
+    pub dry_run: bool,
+    pub snapshots: Vec<String>,
+}
+
+/// Head of the node, or why it could not be fetched
+#[derive(Debug, Serialize)]
+pub struct TestRpcOutput {
+    pub reachable: bool,
+    pub block_hash: Option<String>,
+    pub level: Option<i32>,
+    pub error: Option<String>,
+}
+
+impl CommandOutput for TestRpcOutput {
+    fn text(&self) -> String {
+        match (&self.block_hash, self.level, &self.error) {
+            (Some(block_hash), Some(level), _) => format!("Node head {} at level {}", block_hash, level),
+            (_, _, Some(error)) => format!("The node is not reachable: {}", error),
+            _ => String::from("The node is not reachable"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CleanupContainersOutput {
+    pub dry_run: bool,
+    pub containers: Vec<String>,
+}
+
+impl CommandOutput for CleanupContainersOutput {
+    fn text(&self) -> String {
+        let action = if self.dry_run { "Would remove" } else { "Removed" };
+        let mut lines: Vec<String> = self
+            .containers
+            .iter()
+            .map(|container| format!("{} {}", action, container))
+            .collect();
+        lines.push(format!("{} {} container(s)", action, self.containers.len()));
+        lines.join("\n")
+    }
+}
+
+impl CommandOutput for PruneOutput {
+    fn text(&self) -> String {
+        let action = if self.dry_run { "Would remove" } else { "Removed" };
+        let mut lines: Vec<String> = self
+            .snapshots
+            .iter()
+            .map(|snapshot| format!("{} {}", action, snapshot))
+            .collect();
+        lines.push(format!("{} {} snapshot(s)", action, self.snapshots.len()));
+        lines.join("\n")
+    }
+}
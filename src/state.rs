@@ -0,0 +1,90 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use crate::manifest;
+
+/// Name of the state file kept in the snapshot target directory across restarts
+pub const STATE_FILE_NAME: &str = ".snapshot-state.json";
+
+/// Number of the most recent snapshot durations kept for the trend statistics
+pub const DURATION_HISTORY_SIZE: usize = 100;
+
+/// State of the controller persisted across restarts
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotState {
+    // durations in seconds of the most recent successful snapshots, the oldest first
+    #[serde(default)]
+    pub durations: VecDeque<u64>,
+}
+
+pub type SharedState = Arc<RwLock<SnapshotState>>;
+
+/// Summary of the recent snapshot durations, a growing p95 hints at the database outgrowing the schedule
+#[derive(Clone, Debug, Serialize)]
+pub struct DurationStats {
+    pub count: usize,
+    pub min_secs: u64,
+    pub max_secs: u64,
+    pub avg_secs: u64,
+    pub p95_secs: u64,
+}
+
+impl SnapshotState {
+    /// Reads the state from the directory, a missing state file is an empty state
+    pub fn read(directory: &Path) -> Result<Self, std::io::Error> {
+        let state_path = directory.join(STATE_FILE_NAME);
+        if !state_path.exists() {
+            return Ok(Self::default());
+        }
+        let mut state: Self = serde_json::from_slice(&fs::read(state_path)?)?;
+        state.truncate_durations();
+        Ok(state)
+    }
+
+    /// Writes the state into the directory, the rename makes the update atomic
+    pub fn write(&self, directory: &Path) -> Result<(), std::io::Error> {
+        let state_path = directory.join(STATE_FILE_NAME);
+        let state_path_temp = manifest::sidecar_path(&state_path, "temp");
+        fs::write(&state_path_temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(state_path_temp, state_path)
+    }
+
+    /// Appends the duration, dropping the oldest ones beyond the history size
+    pub fn record_duration(&mut self, secs: u64) {
+        self.durations.push_back(secs);
+        self.truncate_durations();
+    }
+
+    fn truncate_durations(&mut self) {
+        while self.durations.len() > DURATION_HISTORY_SIZE {
+            self.durations.pop_front();
+        }
+    }
+
+    /// The statistics of the kept durations, none before the first snapshot
+    pub fn duration_stats(&self) -> Option<DurationStats> {
+        let mut durations: Vec<u64> = self.durations.iter().copied().collect();
+        durations.sort_unstable();
+        let count = durations.len();
+        if count == 0 {
+            return None;
+        }
+        // nearest rank, the smallest duration at least 95% of the snapshots did not exceed
+        let p95_rank = (count * 95).div_ceil(100);
+        Some(DurationStats {
+            count,
+            min_secs: durations[0],
+            max_secs: durations[count - 1],
+            avg_secs: durations.iter().sum::<u64>() / count as u64,
+            p95_secs: durations[p95_rank.max(1) - 1],
+        })
+    }
+}
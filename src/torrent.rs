@@ -0,0 +1,283 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+/// Extension of the BitTorrent metainfo sidecar written next to a snapshot
+pub const TORRENT_EXTENSION: &str = "torrent";
+
+#[cfg(feature = "torrent")]
+pub use metainfo::write_torrent;
+
+#[cfg(not(feature = "torrent"))]
+pub fn write_torrent(
+    _snapshot_path: &std::path::Path,
+    _trackers: &[url::Url],
+    _web_seeds: &[String],
+) -> Result<String, std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Built without the torrent feature",
+    ))
+}
+
+#[cfg(feature = "torrent")]
+mod metainfo {
+    use std::{
+        collections::BTreeMap,
+        fs,
+        io::{self, Read},
+        path::{Path, PathBuf},
+    };
+
+    use chrono::Utc;
+    use url::{form_urlencoded, Url};
+
+    use super::TORRENT_EXTENSION;
+    use crate::manifest;
+
+    /// Bounds of the piece size, picked to keep a torrent of a large archive at a few thousand pieces
+    const MIN_PIECE_LENGTH: u64 = 256 * 1024;
+    const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+    const TARGET_PIECES: u64 = 2000;
+
+    /// A bencoded value, the dictionary keys are kept in the raw byte order the format requires
+    enum Bencode {
+        Int(i64),
+        Bytes(Vec<u8>),
+        List(Vec<Bencode>),
+        Dict(BTreeMap<&'static str, Bencode>),
+    }
+
+    impl Bencode {
+        fn string(value: &str) -> Self {
+            Bencode::Bytes(value.as_bytes().to_vec())
+        }
+
+        fn encode(&self, out: &mut Vec<u8>) {
+            match self {
+                Bencode::Int(value) => out.extend_from_slice(format!("i{}e", value).as_bytes()),
+                Bencode::Bytes(value) => {
+                    out.extend_from_slice(format!("{}:", value.len()).as_bytes());
+                    out.extend_from_slice(value);
+                }
+                Bencode::List(values) => {
+                    out.push(b'l');
+                    values.iter().for_each(|value| value.encode(out));
+                    out.push(b'e');
+                }
+                Bencode::Dict(entries) => {
+                    out.push(b'd');
+                    for (key, value) in entries {
+                        Bencode::string(key).encode(out);
+                        value.encode(out);
+                    }
+                    out.push(b'e');
+                }
+            }
+        }
+    }
+
+    /// Writes the BitTorrent v1 metainfo of the snapshot into a sidecar and returns its magnet link. An archive is a
+    /// single file torrent, a snapshot directory a multi file one. The web seeds are the urls of the directory the
+    /// snapshot is served from (BEP 19)
+    pub fn write_torrent(snapshot_path: &Path, trackers: &[Url], web_seeds: &[String]) -> Result<String, io::Error> {
+        let name = snapshot_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let files = if snapshot_path.is_dir() {
+            let mut files = vec![];
+            collect_files(snapshot_path, &mut files)?;
+            files.sort();
+            files
+        } else {
+            vec![snapshot_path.to_path_buf()]
+        };
+
+        let lengths = files
+            .iter()
+            .map(|file| fs::metadata(file).map(|metadata| metadata.len()))
+            .collect::<Result<Vec<u64>, io::Error>>()?;
+        let total_length: u64 = lengths.iter().sum();
+        let piece_length = piece_length(total_length);
+
+        let mut info = BTreeMap::new();
+        info.insert("name", Bencode::string(&name));
+        info.insert("piece length", Bencode::Int(piece_length as i64));
+        info.insert("pieces", Bencode::Bytes(hash_pieces(&files, piece_length)?));
+        if snapshot_path.is_dir() {
+            let entries = files
+                .iter()
+                .zip(&lengths)
+                .map(|(file, length)| {
+                    let mut entry = BTreeMap::new();
+                    entry.insert("length", Bencode::Int(*length as i64));
+                    let relative = file.strip_prefix(snapshot_path).unwrap_or(file);
+                    entry.insert(
+                        "path",
+                        Bencode::List(
+                            relative
+                                .components()
+                                .map(|component| Bencode::string(&component.as_os_str().to_string_lossy()))
+                                .collect(),
+                        ),
+                    );
+                    Bencode::Dict(entry)
+                })
+                .collect();
+            info.insert("files", Bencode::List(entries));
+        } else {
+            info.insert("length", Bencode::Int(total_length as i64));
+        }
+        let info = Bencode::Dict(info);
+
+        let mut encoded_info = vec![];
+        info.encode(&mut encoded_info);
+        let info_hash = sha1::digest(&encoded_info)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let mut metainfo = BTreeMap::new();
+        if let Some(tracker) = trackers.first() {
+            metainfo.insert("announce", Bencode::string(tracker.as_str()));
+            // every tracker in a tier of its own, they are tried in order
+            metainfo.insert(
+                "announce-list",
+                Bencode::List(
+                    trackers
+                        .iter()
+                        .map(|tracker| Bencode::List(vec![Bencode::string(tracker.as_str())]))
+                        .collect(),
+                ),
+            );
+        }
+        if !web_seeds.is_empty() {
+            metainfo.insert(
+                "url-list",
+                Bencode::List(web_seeds.iter().map(|web_seed| Bencode::string(web_seed)).collect()),
+            );
+        }
+        metainfo.insert("created by", Bencode::string(concat!("tezedge-snapshots ", env!("CARGO_PKG_VERSION"))));
+        metainfo.insert("creation date", Bencode::Int(Utc::now().timestamp()));
+        metainfo.insert("info", info);
+
+        let mut encoded = vec![];
+        Bencode::Dict(metainfo).encode(&mut encoded);
+        let torrent_path = manifest::sidecar_path(snapshot_path, TORRENT_EXTENSION);
+        let torrent_path_temp = manifest::sidecar_path(&torrent_path, "temp");
+        fs::write(&torrent_path_temp, encoded)?;
+        fs::rename(torrent_path_temp, torrent_path)?;
+
+        let mut magnet = format!(
+            "magnet:?xt=urn:btih:{}&dn={}",
+            info_hash,
+            form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>()
+        );
+        for tracker in trackers {
+            magnet.push_str("&tr=");
+            magnet.extend(form_urlencoded::byte_serialize(tracker.as_str().as_bytes()));
+        }
+        for web_seed in web_seeds {
+            magnet.push_str("&ws=");
+            magnet.extend(form_urlencoded::byte_serialize(web_seed.as_bytes()));
+        }
+        Ok(magnet)
+    }
+
+    fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect_files(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// The smallest power of two piece size keeping the torrent within the target piece count, within the bounds
+    fn piece_length(total_length: u64) -> u64 {
+        let mut piece_length = MIN_PIECE_LENGTH;
+        while piece_length < MAX_PIECE_LENGTH && total_length / piece_length > TARGET_PIECES {
+            piece_length *= 2;
+        }
+        piece_length
+    }
+
+    /// The concatenated SHA-1 hashes of the pieces, the files are hashed as one stream in order
+    fn hash_pieces(files: &[PathBuf], piece_length: u64) -> Result<Vec<u8>, io::Error> {
+        let mut pieces = vec![];
+        let mut piece = Vec::with_capacity(piece_length as usize);
+        for file in files {
+            let mut file = fs::File::open(file)?;
+            loop {
+                let missing = piece_length as usize - piece.len();
+                (&mut file).take(missing as u64).read_to_end(&mut piece)?;
+                if piece.len() == piece_length as usize {
+                    pieces.extend_from_slice(&sha1::digest(&piece));
+                    piece.clear();
+                } else {
+                    // the file ended within the piece, the next file continues it
+                    break;
+                }
+            }
+        }
+        if !piece.is_empty() {
+            pieces.extend_from_slice(&sha1::digest(&piece));
+        }
+        Ok(pieces)
+    }
+
+    /// SHA-1 as used by the BitTorrent v1 piece and info hashes, not for anything security relevant
+    mod sha1 {
+        pub fn digest(data: &[u8]) -> [u8; 20] {
+            let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+            let mut message = data.to_vec();
+            message.push(0x80);
+            while message.len() % 64 != 56 {
+                message.push(0);
+            }
+            message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+            for block in message.chunks(64) {
+                let mut w = [0u32; 80];
+                for (i, word) in block.chunks(4).enumerate() {
+                    w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+                }
+                for i in 16..80 {
+                    w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+                }
+
+                let [mut a, mut b, mut c, mut d, mut e] = state;
+                for (i, word) in w.iter().enumerate() {
+                    let (f, k) = match i {
+                        0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                        20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                        _ => (b ^ c ^ d, 0xCA62C1D6),
+                    };
+                    let temp = a
+                        .rotate_left(5)
+                        .wrapping_add(f)
+                        .wrapping_add(e)
+                        .wrapping_add(k)
+                        .wrapping_add(*word);
+                    e = d;
+                    d = c;
+                    c = b.rotate_left(30);
+                    b = a;
+                    a = temp;
+                }
+
+                for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+                    *state = state.wrapping_add(value);
+                }
+            }
+
+            let mut digest = [0u8; 20];
+            for (chunk, value) in digest.chunks_mut(4).zip(state.iter()) {
+                chunk.copy_from_slice(&value.to_be_bytes());
+            }
+            digest
+        }
+    }
+}
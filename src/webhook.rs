@@ -0,0 +1,57 @@
+// Copyright (c) SimpleStaking, Viable Systems and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use chrono::Utc;
+use serde::Serialize;
+use slog::{warn, Logger};
+use url::Url;
+
+/// Body posted to the webhook
+#[derive(Debug, Serialize)]
+struct WebhookEvent<'a> {
+    status: &'a str,
+    message: &'a str,
+    timestamp: String,
+}
+
+/// Posts snapshotting events to the configured webhook, a no-op when none is configured
+#[derive(Clone)]
+pub struct Webhook {
+    url: Option<Url>,
+    client: reqwest::Client,
+    log: Logger,
+}
+
+impl Webhook {
+    pub fn new(url: Option<Url>, log: Logger) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+            log,
+        }
+    }
+
+    /// Sends the event, a failed delivery only warns
+    pub async fn notify(&self, status: &str, message: &str) {
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+
+        let event = WebhookEvent {
+            status,
+            message,
+            timestamp: Utc::now().to_rfc3339(),
+        };
+        let result = self
+            .client
+            .post(url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warn!(self.log, "Failed to deliver the {} webhook: {}", status, e);
+        }
+    }
+}